@@ -0,0 +1,143 @@
+use std::{
+    backtrace::Backtrace,
+    env,
+    fs::{self, File},
+    io::{self, stdout, BufWriter, Write},
+    panic,
+    path::{Path, PathBuf},
+    process,
+    sync::Mutex,
+};
+
+use crossterm::{
+    cursor,
+    event::{DisableBracketedPaste, DisableMouseCapture},
+    execute,
+    terminal::{self, LeaveAlternateScreen},
+};
+use ropey::Rope;
+
+/// Unsaved buffers, they are written to disk if the editor panics.
+struct Emergency {
+    filename: Option<PathBuf>,
+    text: Rope,
+}
+
+lazy_static! {
+    static ref EMERGENCY: Mutex<Vec<Emergency>> = Mutex::new(Vec::new());
+}
+
+/// Remember the buffers which have unsaved changes, as (file name, text).
+/// Cloning a rope is cheap, the text is shared.
+pub fn keep_buffers(buffers: Vec<(Option<PathBuf>, Rope)>) {
+    if let Ok(mut emergency) = EMERGENCY.lock() {
+        *emergency = buffers
+            .into_iter()
+            .map(|(filename, text)| Emergency { filename, text })
+            .collect();
+    }
+}
+
+/// Put the terminal back to the state before the editor started.
+pub fn restore_terminal() {
+    let _ = execute!(
+        stdout(),
+        DisableBracketedPaste,
+        DisableMouseCapture,
+        LeaveAlternateScreen,
+        cursor::Show
+    );
+    let _ = terminal::disable_raw_mode();
+}
+
+/// On panic, restore the terminal first so that the message is readable,
+/// then write a crash report with backtrace and an emergency copy of the unsaved buffer.
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        default_hook(info);
+
+        let report = env::temp_dir().join(format!("pound-crash-{}.txt", process::id()));
+        let contents = format!("{}\n\nBacktrace:\n{}\n", info, Backtrace::force_capture());
+        match create(&report, 0o600, true).and_then(|mut file| file.write_all(contents.as_bytes()))
+        {
+            Ok(()) => eprintln!("Crash report is written to {}", report.display()),
+            Err(err) => eprintln!("Can't write crash report: {}", err),
+        }
+
+        // The lock is never waited for, the panic may happen while it's held.
+        let emergency = EMERGENCY
+            .try_lock()
+            .map(|mut it| std::mem::take(&mut *it))
+            .unwrap_or_default();
+        for (i, Emergency { filename, text }) in emergency.into_iter().enumerate() {
+            match write_emergency_copy(filename.as_deref(), &text, i) {
+                Ok(path) => eprintln!("Unsaved changes are written to {}", path.display()),
+                Err(err) => eprintln!("Can't write unsaved changes: {}", err),
+            }
+        }
+    }));
+}
+
+/// Write `text` next to the file as `file.pound-emergency`, or to the temp directory if it can't be written there.
+/// `index` tells buffers apart in the temp directory.
+/// The copy can be read by the same users as the file, only by the owner if it's in the temp directory.
+fn write_emergency_copy(filename: Option<&Path>, text: &Rope, index: usize) -> io::Result<PathBuf> {
+    let in_temp_dir =
+        env::temp_dir().join(format!("pound-emergency-{}-{}.txt", process::id(), index));
+    let candidates = filename
+        .map(|it| {
+            let mut name = it.as_os_str().to_owned();
+            name.push(".pound-emergency");
+            (PathBuf::from(name), file_mode(it), false)
+        })
+        .into_iter()
+        .chain([(in_temp_dir, 0o600, true)]);
+    let mut result = Err(io::Error::other("nowhere to write"));
+    for (path, mode, create_new) in candidates {
+        result = create(&path, mode, create_new).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            for chunk in text.chunks() {
+                writer.write_all(chunk.as_bytes())?;
+            }
+            writer.flush()?;
+            Ok(path)
+        });
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+/// Create `path` with permissions `mode`, it isn't overwritten if `create_new` is true.
+/// Files in the temp directory are created new, another user may have put a file or a link there.
+fn create(path: &Path, mode: u32, create_new: bool) -> io::Result<File> {
+    let mut options = File::options();
+    options.write(true);
+    if create_new {
+        options.create_new(true);
+    } else {
+        options.create(true).truncate(true);
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+    #[cfg(not(unix))]
+    let _ = mode;
+    options.open(path)
+}
+
+/// Permissions of `file` without the executable bits, or only the owner can read and write if it doesn't exist.
+fn file_mode(file: &Path) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(metadata) = fs::metadata(file) {
+            return metadata.permissions().mode() & 0o666;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = file;
+    0o600
+}
@@ -1,168 +1,248 @@
 use std::{cmp, path::PathBuf};
 
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
 
-use crate::editor::output::EditorRows;
-
-use self::{output::Output, reader::Reader};
+use self::{
+    keymap::{Command, Keymap, Lookup},
+    output::{view::SplitDirection, Output},
+    reader::Reader,
+};
 
+pub mod config;
+pub mod keymap;
 pub mod output;
 pub mod reader;
 
-static QUIT_TIMES: u8 = 3;
-
 pub(crate) struct Editor {
     reader: Reader,
     output: Output,
+    keymap: Keymap,
     quit_times: u8,
 }
 
 impl Editor {
     pub fn new() -> Self {
+        // 设置要在创建缓冲区之前加载
+        let config_error = config::load();
+        let (keymap, keymap_error) = Keymap::load();
+        let help = Some(config::get().help.clone())
+            .filter(|it| !it.is_empty())
+            .unwrap_or_else(|| keymap.help());
+        let mut output = Output::new(help);
+        let errors: Vec<String> = config_error.into_iter().chain(keymap_error).collect();
+        if !errors.is_empty() {
+            output.status_message.set_message(errors.join("; "));
+        }
         Self {
             reader: Reader,
-            output: Output::new(),
-            quit_times: QUIT_TIMES,
+            output,
+            keymap,
+            quit_times: config::get().quit_times,
         }
     }
 
     /// This is a processor
     fn process_key(&mut self) -> crossterm::Result<bool> {
         // get key
-        if let Ok(key_event) = self.reader.read_key() {
-            match key_event {
-                KeyEvent {
-                    code: KeyCode::Char('q'),
-                    modifiers: KeyModifiers::CONTROL,
-                } => {
-                    /* add following */
-                    if self.output.dirty > 0 && self.quit_times > 0 {
-                        self.output.status_message.set_message(
-                            format!(
-                            "WARNING!!! File has unsaved changes. Press Ctrl-Q {} more times to quit.",
-                            self.quit_times
-                            )
-                        );
-                        self.quit_times -= 1;
-                        return Ok(true);
-                    }
-                    /* end */
-                    return Ok(false);
+        let event = loop {
+            match self.reader.read_event() {
+                // 没有输入时也把未保存的修改写入交换文件
+                Ok(None) => self.output.update_swap(),
+                Ok(Some(event)) => break Ok(event),
+                Err(err) => break Err(err),
+            }
+        };
+        if let Ok(Event::Resize(columns, rows)) = event {
+            self.output.resize((columns as usize, rows as usize));
+            return Ok(true);
+        }
+        if let Ok(Event::Paste(text)) = event {
+            self.output.insert_str(&text);
+            return Ok(true);
+        }
+        if let Ok(Event::Mouse(mouse_event)) = event {
+            self.output.mouse(mouse_event);
+            return Ok(true);
+        }
+        if let Ok(Event::Key(key_event)) = event {
+            match self.keymap.lookup(key_event) {
+                Lookup::Command(command) => return self.execute(command),
+                // 等待按键序列的下一个键, 例如 Ctrl-X Ctrl-S 中的 Ctrl-S
+                Lookup::Pending(keys) => {
+                    self.output.status_message.set_message(keys + "-");
+                    return Ok(true);
                 }
-                KeyEvent {
-                    code:
-                        arrow_key
-                        @
-                        (KeyCode::Up
-                        | KeyCode::Down
-                        | KeyCode::Left
-                        | KeyCode::Right
-                        | KeyCode::Home
-                        | KeyCode::End),
-                    modifiers: KeyModifiers::NONE,
-                } => self.output.move_cursor(arrow_key),
-                KeyEvent {
-                    code: val @ (KeyCode::PageUp | KeyCode::PageDown),
-                    modifiers: KeyModifiers::NONE,
-                } => {
-                    /* add the following */
-                    if matches!(val, KeyCode::PageUp) {
-                        self.output.cursor_controller.cursor_y =
-                            self.output.cursor_controller.row_offset
-                    } else {
-                        self.output.cursor_controller.cursor_y = cmp::min(
-                            self.output.win_size.1 + self.output.cursor_controller.row_offset - 1,
-                            self.output.editor_rows.number_of_rows(),
-                        );
+                Lookup::Unbound(keys) => match key_event {
+                    KeyEvent {
+                        code: code @ (KeyCode::Char(..) | KeyCode::Tab),
+                        modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                        ..
+                    } if keys.len() == 1 => {
+                        self.output.insert_char(match code {
+                            KeyCode::Char(c) => c,
+                            KeyCode::Tab => '\t',
+                            _ => unreachable!(),
+                        });
                     }
-                    /* end */
-                    (0..self.output.win_size.1).for_each(|_| {
-                        self.output.move_cursor(if matches!(val, KeyCode::PageUp) {
-                            KeyCode::Up
-                        } else {
-                            KeyCode::Down
-                        })
-                    });
-                }
-                KeyEvent {
-                    code: KeyCode::Char('s'),
-                    modifiers: KeyModifiers::CONTROL,
-                } => {
-                    use crate::editor::output::SyntaxHighlight;
-                    use crate::prompt;
-                    if matches!(self.output.editor_rows.filename, None) {
-                        let file_name: Option<PathBuf> =
-                            prompt!(&mut self.output, "Save as : {}").map(|p| p.into());
-                        if let None = file_name {
+                    _ => {
+                        if keys.len() > 1 {
                             self.output
                                 .status_message
-                                .set_message("Save Aborted".into());
-                            return Ok(true);
+                                .set_message(format!("{} is not bound", keymap::describe(&keys)));
                         }
-                        use crate::editor::output::Row;
-                        file_name
-                            .as_ref()
-                            .and_then(|path| path.extension())
-                            .and_then(|ext| ext.to_str())
-                            .map(|ext| {
-                                Output::select_syntax(ext).map(|highlight| {
-                                    let highlight = self.output.syntax_highlight.insert(highlight);
-
-                                    for i in 0..self.output.editor_rows.number_of_rows() {
-                                        highlight.update_syntax(
-                                            i,
-                                            &mut self.output.editor_rows.row_contents,
-                                        );
-                                    }
-                                })
-                            });
-
-                        self.output.editor_rows.filename = file_name;
+                        return Ok(true);
                     }
-                    self.output.editor_rows.save().map(|size| {
-                        self.output
-                            .status_message
-                            .set_message(format!("{} bytes written", size));
-                        self.output.dirty = 0;
-                    })?
+                },
+            }
+        }
+        self.quit_times = config::get().quit_times;
+        Ok(true)
+    }
+
+    /// Run a command which is bound to keys.
+    /// Returns false if the editor should quit.
+    fn execute(&mut self, command: Command) -> crossterm::Result<bool> {
+        match command {
+            Command::Quit => {
+                /* add following */
+                let dirty_buffers = self.output.dirty_buffers();
+                if dirty_buffers > 0 && self.quit_times > 0 {
+                    self.output.status_message.set_message(format!(
+                        "WARNING!!! {} buffer(s) have unsaved changes. Press {} {} more times to quit.",
+                        dirty_buffers,
+                        self.keymap.keys_of(Command::Quit),
+                        self.quit_times
+                    ));
+                    self.quit_times -= 1;
+                    return Ok(true);
                 }
-                KeyEvent {
-                    code: KeyCode::Char('f'),
-                    modifiers: KeyModifiers::CONTROL,
-                } => {
-                    self.output.find();
+                /* end */
+                self.output.remove_swap();
+                return Ok(false);
+            }
+            Command::MoveUp => self.output.move_cursor(KeyCode::Up),
+            Command::MoveDown => self.output.move_cursor(KeyCode::Down),
+            Command::MoveLeft => self.output.move_cursor(KeyCode::Left),
+            Command::MoveRight => self.output.move_cursor(KeyCode::Right),
+            Command::MoveHome => self.output.move_cursor(KeyCode::Home),
+            Command::MoveEnd => self.output.move_cursor(KeyCode::End),
+            Command::SelectUp => self.output.select(KeyCode::Up),
+            Command::SelectDown => self.output.select(KeyCode::Down),
+            Command::SelectLeft => self.output.select(KeyCode::Left),
+            Command::SelectRight => self.output.select(KeyCode::Right),
+            Command::SelectHome => self.output.select(KeyCode::Home),
+            Command::SelectEnd => self.output.select(KeyCode::End),
+            Command::ClearSelection => self.output.clear_selection(),
+            Command::PageUp | Command::PageDown => {
+                /* add the following */
+                if command == Command::PageUp {
+                    self.output.buffer.cursor_controller.cursor_y =
+                        self.output.buffer.cursor_controller.row_offset
+                } else {
+                    self.output.buffer.cursor_controller.cursor_y = cmp::min(
+                        self.output.win_size.1 + self.output.buffer.cursor_controller.row_offset
+                            - 1,
+                        self.output.buffer.editor_rows.number_of_rows(),
+                    );
                 }
-                KeyEvent {
-                    code: key @ (KeyCode::Backspace | KeyCode::Delete),
-                    modifiers: KeyModifiers::NONE,
-                } => {
-                    if matches!(key, KeyCode::Delete) {
-                        self.output.move_cursor(KeyCode::Right)
+                /* end */
+                (0..self.output.win_size.1).for_each(|_| {
+                    self.output.move_cursor(if command == Command::PageUp {
+                        KeyCode::Up
+                    } else {
+                        KeyCode::Down
+                    })
+                });
+            }
+            Command::Save => {
+                use crate::prompt;
+                // 只读的文件 (例如含有无效的 UTF-8) 只能保存为另一个文件
+                if self.output.buffer.editor_rows.filename.is_none()
+                    || self.output.buffer.editor_rows.read_only
+                {
+                    let file_name: Option<PathBuf> = prompt!(&mut self.output, "Save as : {}")
+                        .filter(|it| !it.is_empty())
+                        .map(|p| p.into());
+                    if file_name.is_none() {
+                        self.output
+                            .status_message
+                            .set_message("Save Aborted".into());
+                        return Ok(true);
                     }
-                    self.output.delete_char()
+                    if let Some(highlight) = file_name
+                        .as_ref()
+                        .and_then(|path| path.extension())
+                        .and_then(|ext| ext.to_str())
+                        .and_then(Output::select_syntax)
+                    {
+                        self.output
+                            .buffer
+                            .editor_rows
+                            .set_syntax_highlight(highlight);
+                    }
+
+                    self.output.buffer.editor_rows.filename = file_name;
+                    self.output.buffer.editor_rows.read_only = false;
+                    self.output.buffer.editor_rows.apply_editorconfig();
                 }
-                KeyEvent {
-                    code: KeyCode::Enter,
-                    modifiers: KeyModifiers::NONE,
-                } => self.output.insert_newline(),
-                KeyEvent {
-                    code: code @ (KeyCode::Char(..) | KeyCode::Tab),
-                    modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
-                } => {
-                    self.output.insert_char(match code {
-                        KeyCode::Char(c) => c,
-                        KeyCode::Tab => '\t',
-                        _ => unreachable!(),
-                    });
+                self.output.trim_trailing_whitespace();
+                // 保存失败时只显示错误, 编辑器继续运行, 修改也不会丢失
+                match self.output.buffer.editor_rows.save() {
+                    Ok(written) => {
+                        self.output
+                            .status_message
+                            .set_message(match written.in_place {
+                                Some(backup) => format!(
+                                    "{} bytes written in place to keep the owner, backup in {}",
+                                    written.bytes,
+                                    backup.display()
+                                ),
+                                None => format!("{} bytes written", written.bytes),
+                            });
+                        self.output.buffer.dirty = 0;
+                        self.output.buffer.history.mark_saved();
+                    }
+                    Err(err) => self
+                        .output
+                        .status_message
+                        .set_message(format!("Can't save! I/O error: {}", err)),
                 }
-                _ => return Ok(true),
-            };
+            }
+            Command::Find => self.output.find()?,
+            Command::Replace => self.output.replace()?,
+            Command::Open => self.output.open_file()?,
+            Command::NextBuffer => self.output.next_buffer(),
+            Command::PreviousBuffer => self.output.previous_buffer(),
+            Command::PickBuffer => self.output.pick_buffer()?,
+            Command::SplitHorizontal => self.output.split_view(SplitDirection::Horizontal),
+            Command::SplitVertical => self.output.split_view(SplitDirection::Vertical),
+            Command::CloseView => self.output.close_view(),
+            Command::NextView => self.output.next_view(),
+            Command::ToggleLineNumbers => self.output.toggle_line_numbers(),
+            Command::ToggleWrap => self.output.toggle_wrap(),
+            Command::ToggleLineEnding => self.output.toggle_line_ending(),
+            Command::Set => self.output.set_option()?,
+            Command::Indent => self.output.indent(),
+            Command::Dedent => self.output.dedent(),
+            Command::Undo => self.output.undo(),
+            Command::Redo => self.output.redo(),
+            Command::SelectAll => self.output.select_all(),
+            Command::Copy => {
+                self.output.copy();
+            }
+            Command::Cut => self.output.cut(),
+            Command::Paste => self.output.paste(),
+            Command::DeleteBackward => self.output.delete_char(),
+            Command::DeleteForward => self.output.delete_forward(),
+            Command::Newline => self.output.insert_newline(),
         }
-        self.quit_times = QUIT_TIMES;
+        self.quit_times = config::get().quit_times;
         Ok(true)
     }
 
     pub fn run(&mut self) -> crossterm::Result<bool> {
+        self.output.recover_swap()?;
+        self.output.update_swap();
         self.output.refresh_screen()?;
         self.process_key()
     }
@@ -171,24 +251,43 @@ impl Editor {
 #[macro_export]
 macro_rules! prompt {
     ($output:expr,$args:tt) => {
-        prompt!($output, $args, callback = |&_, _, _| {})
+        prompt!($output, $args, callback = |&_, _, _| String::new())
     };
     ($output:expr,$args:tt, callback = $callback:expr) => {{
-        use crate::editor::{output::Output, reader::Reader};
-        use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+        use $crate::editor::{output::Output, reader::Reader};
+        use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
 
         let output: &mut Output = $output;
         let mut input = String::with_capacity(32);
+        let mut status = String::new(); // Returned by callback, shown after the prompt.
+        let mut cancelled = false;
         loop {
-            output.status_message.set_message(format!($args, input));
+            output
+                .status_message
+                .set_message(format!($args, input) + " " + &status);
             output.refresh_screen().unwrap();
-            let key_event = Reader.read_key()?;
+            let key_event = match Reader.read_event()? {
+                Some(Event::Key(key_event)) => key_event,
+                Some(Event::Resize(columns, rows)) => {
+                    output.resize((columns as usize, rows as usize));
+                    continue;
+                }
+                // 粘贴到提示框的文本只有一行, 去掉换行等控制字符
+                Some(Event::Paste(text)) => {
+                    input.extend(text.chars().filter(|c| !c.is_control()));
+                    status = $callback(output, &input, KeyEvent::from(KeyCode::Null));
+                    continue;
+                }
+                _ => continue,
+            };
             match key_event {
                 KeyEvent {
                     code: KeyCode::Char(c),
                     modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                    ..
                 } => {
                     if c == '\x1b' {
+                        cancelled = true;
                         break;
                     }
                     input.push(c);
@@ -198,26 +297,29 @@ macro_rules! prompt {
                      .. // omit
                 } => {
                     output.status_message.set_message(String::new());
-                    input.clear();
+                    cancelled = true;
                     break;
                 }
                 KeyEvent {
                     code: KeyCode::Delete | KeyCode::Backspace,
                     modifiers: KeyModifiers::NONE,
+                    ..
                 } => {
                     input.pop();
                 }
                 KeyEvent {
                     code: KeyCode::Enter,
                     modifiers: KeyModifiers::NONE,
+                    ..
                 } => break,
                 _ => {}
             }
 
-            $callback(output, &input, key_event.code);
+            status = $callback(output, &input, key_event);
         }
 
-        if input.is_empty() {
+        // ESC 取消输入时返回 None, 按下 Enter 时返回输入的内容 (可能为空)
+        if cancelled {
             None
         } else {
             Some(input)
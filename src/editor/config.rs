@@ -0,0 +1,277 @@
+use std::{
+    env, fs, io,
+    path::PathBuf,
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
+};
+
+use toml::{Table, Value};
+
+use super::output::{cursor_controller::Wrap, indent::Indent, view::LineNumbers};
+
+static PROJECT_FILE: &str = ".pound.toml"; // Searched for in the current directory and its parents.
+
+/// Settings of the editor, which are read from `config.toml` in config directory,
+/// then from `.pound.toml` of the project which overrides it. Settings can be changed at runtime by `:set`.
+///     tab_stop = 4
+///     wrap = "word"
+#[derive(Clone)]
+pub(crate) struct Config {
+    pub tab_stop: usize,
+    pub indent: Indent, // Used when it can't be detected from the file, "tab" or number of spaces in config file.
+    pub quit_times: u8, // Times quit must be pressed again when there are unsaved changes.
+    pub message_timeout: Duration, // How long a message stays in status bar, in seconds in config file.
+    pub swap_interval: Duration,   // Unsaved changes are written to swap file at most this often.
+    pub scroll_lines: usize,       // Lines scrolled by a step of mouse wheel.
+    pub backup: bool,              // Keep the old file as `file~` when saving.
+    pub line_numbers: LineNumbers,
+    pub wrap: Wrap,
+    pub help: String, // Shown in status bar at startup, it's made from the keymap if it's empty.
+    // Commands of clipboard, e.g. ["xsel", "-bi"] or "xsel -bi". They are detected if they are empty.
+    pub copy_command: Vec<String>,
+    pub paste_command: Vec<String>,
+}
+
+static NAMES: &[&str] = &[
+    "tab_stop",
+    "indent",
+    "quit_times",
+    "message_timeout",
+    "swap_interval",
+    "scroll_lines",
+    "backup",
+    "line_numbers",
+    "wrap",
+    "help",
+    "copy_command",
+    "paste_command",
+];
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tab_stop: 8,
+            indent: Indent::Tab,
+            quit_times: 3,
+            message_timeout: Duration::from_secs(5),
+            swap_interval: Duration::from_secs(4),
+            scroll_lines: 3,
+            backup: false,
+            line_numbers: LineNumbers::Off,
+            wrap: Wrap::Off,
+            copy_command: Vec::new(),
+            paste_command: Vec::new(),
+            help: String::new(),
+        }
+    }
+}
+
+lazy_static! {
+    static ref CONFIG: RwLock<Arc<Config>> = RwLock::new(Arc::new(Config::default()));
+}
+
+/// Current settings.
+pub(crate) fn get() -> Arc<Config> {
+    Arc::clone(&CONFIG.read().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// Change the settings, they are seen by the following `get()`.
+pub(crate) fn update<T>(f: impl FnOnce(&mut Config) -> T) -> T {
+    let mut config = CONFIG.write().unwrap_or_else(PoisonError::into_inner);
+    f(Arc::make_mut(&mut config))
+}
+
+impl Config {
+    /// Apply the settings in a config file, invalid ones are left unchanged.
+    fn apply(&mut self, text: &str) -> Result<(), String> {
+        let table: Table = toml::from_str(text).map_err(|err| err.message().to_string())?;
+        let errors: Vec<String> = table
+            .iter()
+            .filter_map(|(name, value)| self.set(name, value).err())
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    fn set(&mut self, name: &str, value: &Value) -> Result<(), String> {
+        let invalid = |expected: &str| format!("{} should be {}", name, expected);
+        match name {
+            "tab_stop" => {
+                self.tab_stop = number(value)
+                    .filter(|it| *it > 0)
+                    .ok_or_else(|| invalid("a positive number"))?
+            }
+            "indent" => {
+                self.indent = match value {
+                    Value::String(it) if it == "tab" => Indent::Tab,
+                    _ => Indent::Spaces(
+                        number(value)
+                            .filter(|it| (1..=16).contains(it))
+                            .ok_or_else(|| invalid("\"tab\" or a number of spaces up to 16"))?,
+                    ),
+                }
+            }
+            "quit_times" => {
+                self.quit_times = number(value).ok_or_else(|| invalid("a number up to 255"))?
+            }
+            "message_timeout" => {
+                self.message_timeout = number(value)
+                    .map(Duration::from_secs)
+                    .ok_or_else(|| invalid("seconds"))?
+            }
+            "swap_interval" => {
+                self.swap_interval = number(value)
+                    .map(Duration::from_secs)
+                    .ok_or_else(|| invalid("seconds"))?
+            }
+            "scroll_lines" => {
+                self.scroll_lines = number(value)
+                    .filter(|it| *it > 0)
+                    .ok_or_else(|| invalid("a positive number"))?
+            }
+            "backup" => self.backup = value.as_bool().ok_or_else(|| invalid("true or false"))?,
+            "line_numbers" => {
+                self.line_numbers = value
+                    .as_str()
+                    .and_then(LineNumbers::from_name)
+                    .ok_or_else(|| invalid("\"off\", \"absolute\" or \"relative\""))?
+            }
+            "wrap" => {
+                self.wrap = value
+                    .as_str()
+                    .and_then(Wrap::from_name)
+                    .ok_or_else(|| invalid("\"off\", \"char\" or \"word\""))?
+            }
+            "help" => self.help = value.as_str().ok_or_else(|| invalid("a string"))?.into(),
+            "copy_command" => {
+                self.copy_command = command(value).ok_or_else(|| invalid("a command"))?
+            }
+            "paste_command" => {
+                self.paste_command = command(value).ok_or_else(|| invalid("a command"))?
+            }
+            _ => return Err(format!("unknown setting \"{}\"", name)),
+        }
+        Ok(())
+    }
+
+    /// Value of setting `name` as it's written in config file.
+    fn value(&self, name: &str) -> Option<Value> {
+        Some(match name {
+            "tab_stop" => Value::Integer(self.tab_stop as i64),
+            "indent" => match self.indent {
+                Indent::Tab => Value::String("tab".into()),
+                Indent::Spaces(width) => Value::Integer(width as i64),
+            },
+            "quit_times" => Value::Integer(self.quit_times.into()),
+            "message_timeout" => Value::Integer(self.message_timeout.as_secs() as i64),
+            "swap_interval" => Value::Integer(self.swap_interval.as_secs() as i64),
+            "scroll_lines" => Value::Integer(self.scroll_lines as i64),
+            "backup" => Value::Boolean(self.backup),
+            "line_numbers" => Value::String(self.line_numbers.name().into()),
+            "wrap" => Value::String(self.wrap.key().into()),
+            "help" => Value::String(self.help.clone()),
+            "copy_command" => command_value(&self.copy_command),
+            "paste_command" => command_value(&self.paste_command),
+            _ => return None,
+        })
+    }
+}
+
+/// Integer `value` if it fits in `T`.
+fn number<T: TryFrom<i64>>(value: &Value) -> Option<T> {
+    value.as_integer().and_then(|it| T::try_from(it).ok())
+}
+
+/// Program and arguments of a command, which is a list of strings, or a string split at whitespace.
+fn command(value: &Value) -> Option<Vec<String>> {
+    match value {
+        Value::String(it) => Some(it.split_whitespace().map(String::from).collect()),
+        Value::Array(it) => it.iter().map(|it| it.as_str().map(String::from)).collect(),
+        _ => None,
+    }
+}
+
+fn command_value(command: &[String]) -> Value {
+    Value::Array(command.iter().cloned().map(Value::String).collect())
+}
+
+/// Directory of config files, `$XDG_CONFIG_HOME/pound` or `~/.config/pound`.
+pub(crate) fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|it| !it.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("pound"))
+}
+
+/// `.pound.toml` in the current directory or the nearest parent which has one.
+fn project_file() -> Option<PathBuf> {
+    let dir = env::current_dir().ok()?;
+    dir.ancestors()
+        .map(|it| it.join(PROJECT_FILE))
+        .find(|it| it.is_file())
+}
+
+/// Load the user config file and then the project config file.
+/// Returns the errors in them, which don't stop the editor.
+pub(crate) fn load() -> Option<String> {
+    let files: Vec<PathBuf> = config_dir()
+        .map(|dir| dir.join("config.toml"))
+        .into_iter()
+        .chain(project_file())
+        .collect();
+    let mut errors = Vec::new();
+    update(|config| {
+        for path in files {
+            let result = match fs::read_to_string(&path) {
+                Ok(text) => config.apply(&text),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+                Err(err) => Err(err.to_string()),
+            };
+            if let Err(err) = result {
+                errors.push(format!("{}: {}", path.display(), err));
+            }
+        }
+    });
+    Some(errors.join("; ")).filter(|it| !it.is_empty())
+}
+
+/// Run a `:set` command, which is one of
+///     name = value    Value is written as in config file, quotes of a string may be left out.
+///     name value
+///     name            Show the value.
+///     (nothing)       Show all settings.
+/// Returns the message to show.
+pub(crate) fn set(command: &str) -> Result<String, String> {
+    let command = command.trim();
+    let (name, value) = match command.split_once(|c: char| c == '=' || c.is_whitespace()) {
+        Some((name, value)) => {
+            let value = value.trim_start();
+            (name, Some(value.strip_prefix('=').unwrap_or(value).trim()))
+        }
+        None => (command, None),
+    };
+    let config = get();
+    if name.is_empty() {
+        return Ok(NAMES
+            .iter()
+            .map(|name| format!("{}={}", name, config.value(name).unwrap()))
+            .collect::<Vec<_>>()
+            .join(" "));
+    }
+    let Some(current) = config.value(name) else {
+        return Err(format!("Unknown setting \"{}\"", name));
+    };
+    let Some(value) = value.filter(|it| !it.is_empty()) else {
+        return Ok(format!("{} = {}", name, current));
+    };
+    let value = toml::from_str::<Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut it| it.remove("value"))
+        .unwrap_or_else(|| Value::String(value.into()));
+    update(|config| config.set(name, &value))?;
+    Ok(format!("{} = {}", name, get().value(name).unwrap()))
+}
@@ -0,0 +1,539 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use super::config;
+
+/// What the editor does when a key is pressed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) enum Command {
+    Quit,
+    Save,
+    Find,
+    Replace,
+    Open,
+    NextBuffer,
+    PreviousBuffer,
+    PickBuffer,
+    SplitHorizontal,
+    SplitVertical,
+    CloseView,
+    NextView,
+    ToggleLineNumbers,
+    ToggleWrap,
+    ToggleLineEnding,
+    Set,
+    Indent,
+    Dedent,
+    Undo,
+    Redo,
+    SelectAll,
+    Copy,
+    Cut,
+    Paste,
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    MoveHome,
+    MoveEnd,
+    SelectUp,
+    SelectDown,
+    SelectLeft,
+    SelectRight,
+    SelectHome,
+    SelectEnd,
+    PageUp,
+    PageDown,
+    ClearSelection,
+    DeleteBackward,
+    DeleteForward,
+    Newline,
+}
+
+/// Names of commands in keymap file.
+static COMMANDS: &[(&str, Command)] = &[
+    ("quit", Command::Quit),
+    ("save", Command::Save),
+    ("find", Command::Find),
+    ("replace", Command::Replace),
+    ("open", Command::Open),
+    ("next_buffer", Command::NextBuffer),
+    ("previous_buffer", Command::PreviousBuffer),
+    ("pick_buffer", Command::PickBuffer),
+    ("split_horizontal", Command::SplitHorizontal),
+    ("split_vertical", Command::SplitVertical),
+    ("close_view", Command::CloseView),
+    ("next_view", Command::NextView),
+    ("toggle_line_numbers", Command::ToggleLineNumbers),
+    ("toggle_wrap", Command::ToggleWrap),
+    ("toggle_line_ending", Command::ToggleLineEnding),
+    ("set", Command::Set),
+    ("indent", Command::Indent),
+    ("dedent", Command::Dedent),
+    ("undo", Command::Undo),
+    ("redo", Command::Redo),
+    ("select_all", Command::SelectAll),
+    ("copy", Command::Copy),
+    ("cut", Command::Cut),
+    ("paste", Command::Paste),
+    ("move_up", Command::MoveUp),
+    ("move_down", Command::MoveDown),
+    ("move_left", Command::MoveLeft),
+    ("move_right", Command::MoveRight),
+    ("move_home", Command::MoveHome),
+    ("move_end", Command::MoveEnd),
+    ("select_up", Command::SelectUp),
+    ("select_down", Command::SelectDown),
+    ("select_left", Command::SelectLeft),
+    ("select_right", Command::SelectRight),
+    ("select_home", Command::SelectHome),
+    ("select_end", Command::SelectEnd),
+    ("page_up", Command::PageUp),
+    ("page_down", Command::PageDown),
+    ("clear_selection", Command::ClearSelection),
+    ("delete_backward", Command::DeleteBackward),
+    ("delete_forward", Command::DeleteForward),
+    ("newline", Command::Newline),
+];
+
+/// Keys of the editor, a keymap file changes some of them.
+static DEFAULT_KEYMAP: &[(&str, Command)] = &[
+    ("Ctrl-Q", Command::Quit),
+    ("Ctrl-S", Command::Save),
+    ("Ctrl-F", Command::Find),
+    ("Ctrl-R", Command::Replace),
+    ("Ctrl-O", Command::Open),
+    ("Ctrl-N", Command::NextBuffer),
+    ("Ctrl-P", Command::PreviousBuffer),
+    ("Ctrl-B", Command::PickBuffer),
+    ("Alt-S", Command::SplitHorizontal),
+    ("Alt-V", Command::SplitVertical),
+    ("Alt-Q", Command::CloseView),
+    ("Ctrl-W", Command::NextView),
+    ("Alt-N", Command::ToggleLineNumbers),
+    ("Alt-Z", Command::ToggleWrap),
+    ("Ctrl-E", Command::ToggleLineEnding),
+    ("Alt-:", Command::Set),
+    ("Tab", Command::Indent),
+    ("Shift-Tab", Command::Dedent),
+    ("Ctrl-Z", Command::Undo),
+    ("Ctrl-Y", Command::Redo),
+    ("Ctrl-A", Command::SelectAll),
+    ("Ctrl-C", Command::Copy),
+    ("Ctrl-X", Command::Cut),
+    ("Ctrl-V", Command::Paste),
+    ("Up", Command::MoveUp),
+    ("Down", Command::MoveDown),
+    ("Left", Command::MoveLeft),
+    ("Right", Command::MoveRight),
+    ("Home", Command::MoveHome),
+    ("End", Command::MoveEnd),
+    ("Shift-Up", Command::SelectUp),
+    ("Shift-Down", Command::SelectDown),
+    ("Shift-Left", Command::SelectLeft),
+    ("Shift-Right", Command::SelectRight),
+    ("Shift-Home", Command::SelectHome),
+    ("Shift-End", Command::SelectEnd),
+    ("PageUp", Command::PageUp),
+    ("PageDown", Command::PageDown),
+    ("Esc", Command::ClearSelection),
+    ("Backspace", Command::DeleteBackward),
+    ("Delete", Command::DeleteForward),
+    ("Enter", Command::Newline),
+];
+
+/// Commands in the help shown at startup, with their short names.
+static HELP: &[(Command, &str)] = &[
+    (Command::Save, "Save"),
+    (Command::Quit, "Quit"),
+    (Command::Find, "Find"),
+    (Command::Replace, "Replace"),
+    (Command::Open, "Open"),
+    (Command::PickBuffer, "Buffers"),
+    (Command::Undo, "Undo"),
+    (Command::Redo, "Redo"),
+    (Command::Set, "Set"),
+];
+
+static MODIFIERS: &[(&str, KeyModifiers)] = &[
+    ("Ctrl-", KeyModifiers::CONTROL),
+    ("Alt-", KeyModifiers::ALT),
+    ("Shift-", KeyModifiers::SHIFT),
+];
+
+/// Names of keys other than chars.
+static KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Insert", KeyCode::Insert),
+    ("Delete", KeyCode::Delete),
+    ("Backspace", KeyCode::Backspace),
+    ("Enter", KeyCode::Enter),
+    ("Esc", KeyCode::Esc),
+    ("Tab", KeyCode::Tab),
+    ("BackTab", KeyCode::BackTab),
+    ("Space", KeyCode::Char(' ')),
+];
+
+/// A key with modifiers, e.g. Ctrl-S.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub(crate) struct KeyChord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl From<KeyEvent> for KeyChord {
+    fn from(event: KeyEvent) -> Self {
+        let mut modifiers = event.modifiers;
+        // Shift is already in the char (e.g. 'S'), and Shift-Tab is BackTab.
+        if matches!(event.code, KeyCode::Char(_) | KeyCode::BackTab) {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        Self {
+            code: event.code,
+            modifiers,
+        }
+    }
+}
+
+impl KeyChord {
+    /// Parse a key like "Ctrl-S", "Alt-v", "Shift-Up", "PageDown" or "x".
+    /// Letters with Ctrl or Alt are the same in either case, use Shift for the upper case.
+    fn parse(s: &str) -> Option<Self> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut key = s;
+        while let Some((prefix, modifier)) = MODIFIERS.iter().find(|(prefix, _)| {
+            key.len() > prefix.len()
+                && key
+                    .get(..prefix.len())
+                    .is_some_and(|it| it.eq_ignore_ascii_case(prefix))
+        }) {
+            modifiers |= *modifier;
+            key = &key[prefix.len()..];
+        }
+        let code = match KEY_NAMES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+        {
+            Some((_, code)) => *code,
+            None => match key.strip_prefix(['F', 'f']).map(str::parse::<u8>) {
+                Some(Ok(number)) => KeyCode::F(number),
+                _ => {
+                    let mut chars = key.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => KeyCode::Char(c),
+                        _ => return None,
+                    }
+                }
+            },
+        };
+        let code = match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => {
+                KeyCode::Char(c.to_ascii_uppercase())
+            }
+            KeyCode::Char(c) if modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                KeyCode::Char(c.to_ascii_lowercase())
+            }
+            KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+            code => code,
+        };
+        Some(KeyEvent::new(code, modifiers).into())
+    }
+
+    /// The key as it's written in keymap file.
+    fn name(&self) -> String {
+        let mut name = String::new();
+        for (prefix, modifier) in MODIFIERS {
+            if self.modifiers.contains(*modifier) {
+                name.push_str(prefix);
+            }
+        }
+        match KEY_NAMES.iter().find(|(_, code)| *code == self.code) {
+            Some((key, _)) => name.push_str(key),
+            None => match self.code {
+                KeyCode::F(number) => name.push_str(&format!("F{}", number)),
+                KeyCode::Char(c) if self.modifiers.is_empty() => name.push(c),
+                KeyCode::Char(c) => name.push(c.to_ascii_uppercase()),
+                _ => name.push('?'),
+            },
+        }
+        name
+    }
+}
+
+/// Parse keys separated by spaces, e.g. "Ctrl-X Ctrl-S".
+fn parse_keys(s: &str) -> Option<Vec<KeyChord>> {
+    let keys = s
+        .split_whitespace()
+        .map(KeyChord::parse)
+        .collect::<Option<Vec<_>>>()?;
+    Some(keys).filter(|it| !it.is_empty())
+}
+
+pub(crate) fn describe(keys: &[KeyChord]) -> String {
+    keys.iter()
+        .map(KeyChord::name)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Result of pressing a key.
+pub(crate) enum Lookup {
+    Command(Command),
+    Pending(String), // The keys pressed so far start a sequence, e.g. "Ctrl-X" of "Ctrl-X Ctrl-S".
+    Unbound(Vec<KeyChord>),
+}
+
+/// Which keys run which commands.
+/// A binding may be a sequence of keys, a key which starts a sequence isn't a command by itself.
+pub(crate) struct Keymap {
+    bindings: HashMap<Vec<KeyChord>, Command>,
+    pending: Vec<KeyChord>,
+}
+
+impl Keymap {
+    fn default_keymap() -> Self {
+        Self {
+            bindings: DEFAULT_KEYMAP
+                .iter()
+                .map(|(keys, command)| (parse_keys(keys).unwrap(), *command))
+                .collect(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// The default keymap, changed by `keymap.toml` in config directory.
+    /// Returns an error about the file as well, the valid bindings in it are still used.
+    pub(crate) fn load() -> (Self, Option<String>) {
+        let mut keymap = Self::default_keymap();
+        let Some(path) = config::config_dir().map(|dir| dir.join("keymap.toml")) else {
+            return (keymap, None);
+        };
+        let error = match fs::read_to_string(&path) {
+            Ok(text) => keymap.apply(&text).err(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => Some(err.to_string()),
+        };
+        (
+            keymap,
+            error.map(|err| format!("{}: {}", path.display(), err)),
+        )
+    }
+
+    /// Apply the bindings in a keymap file, which are like
+    ///     "Ctrl-X Ctrl-S" = "save"
+    ///     "Ctrl-S" = "none"       # Remove the binding.
+    fn apply(&mut self, text: &str) -> Result<(), String> {
+        let table: BTreeMap<String, String> =
+            toml::from_str(text).map_err(|err| err.message().to_string())?;
+        let mut errors = Vec::new();
+        for (keys, name) in table {
+            let Some(chords) = parse_keys(&keys) else {
+                errors.push(format!("invalid key \"{}\"", keys));
+                continue;
+            };
+            if name == "none" {
+                self.bindings.remove(&chords);
+                continue;
+            }
+            match COMMANDS.iter().find(|(it, _)| *it == name) {
+                Some((_, command)) => {
+                    self.bindings.insert(chords, *command);
+                }
+                None => errors.push(format!("unknown command \"{}\"", name)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    /// Feed a pressed key, it may be a part of a sequence.
+    pub(crate) fn lookup(&mut self, key: KeyEvent) -> Lookup {
+        self.pending.push(key.into());
+        let pending = &self.pending;
+        if self
+            .bindings
+            .keys()
+            .any(|keys| keys.len() > pending.len() && keys.starts_with(pending))
+        {
+            return Lookup::Pending(describe(pending));
+        }
+        let keys = std::mem::take(&mut self.pending);
+        match self.bindings.get(&keys) {
+            Some(command) => Lookup::Command(*command),
+            None => Lookup::Unbound(keys),
+        }
+    }
+
+    /// The shortest keys bound to `command`, for messages like "Press Ctrl-Q to quit".
+    pub(crate) fn keys_of(&self, command: Command) -> String {
+        self.bindings
+            .iter()
+            .filter(|(_, it)| **it == command)
+            .map(|(keys, _)| describe(keys))
+            .min_by_key(|it| (it.len(), it.clone()))
+            .unwrap_or_else(|| {
+                let (name, _) = COMMANDS.iter().find(|(_, it)| *it == command).unwrap();
+                format!("<{}>", name)
+            })
+    }
+
+    /// Help shown at startup, e.g. "HELP: Ctrl-S = Save | Ctrl-Q = Quit".
+    pub(crate) fn help(&self) -> String {
+        let keys: Vec<String> = HELP
+            .iter()
+            .map(|(command, name)| format!("{} = {}", self.keys_of(*command), name))
+            .collect();
+        format!("HELP: {}", keys.join(" | "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    fn command(lookup: Lookup) -> Option<Command> {
+        match lookup {
+            Lookup::Command(command) => Some(command),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parse_key_chords() {
+        let chord = |code, modifiers| Some(KeyChord::from(key(code, modifiers)));
+        assert_eq!(
+            KeyChord::parse("Ctrl-S"),
+            chord(KeyCode::Char('s'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(KeyChord::parse("ctrl-s"), KeyChord::parse("Ctrl-S"));
+        assert_eq!(
+            KeyChord::parse("Alt-V"),
+            chord(KeyCode::Char('v'), KeyModifiers::ALT)
+        );
+        assert_eq!(
+            KeyChord::parse("Shift-a"),
+            chord(KeyCode::Char('A'), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            KeyChord::parse("Shift-Up"),
+            chord(KeyCode::Up, KeyModifiers::SHIFT)
+        );
+        assert_eq!(
+            KeyChord::parse("Shift-Tab"),
+            chord(KeyCode::BackTab, KeyModifiers::SHIFT)
+        );
+        assert_eq!(
+            KeyChord::parse("Ctrl-Alt-x"),
+            chord(
+                KeyCode::Char('x'),
+                KeyModifiers::CONTROL | KeyModifiers::ALT
+            )
+        );
+        assert_eq!(
+            KeyChord::parse("PageDown"),
+            chord(KeyCode::PageDown, KeyModifiers::NONE)
+        );
+        assert_eq!(
+            KeyChord::parse("F5"),
+            chord(KeyCode::F(5), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            KeyChord::parse("Space"),
+            chord(KeyCode::Char(' '), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            KeyChord::parse("x"),
+            chord(KeyCode::Char('x'), KeyModifiers::NONE)
+        );
+        assert!(KeyChord::parse("Ctrl-").is_none());
+        assert!(KeyChord::parse("xy").is_none());
+        assert!(KeyChord::parse("").is_none());
+        assert_eq!(parse_keys("Ctrl-X  Ctrl-S").map(|it| it.len()), Some(2));
+        assert!(parse_keys(" ").is_none());
+    }
+
+    #[test]
+    fn names_are_parsed_back() {
+        for (keys, _) in DEFAULT_KEYMAP {
+            let chords = parse_keys(keys).unwrap();
+            assert_eq!(parse_keys(&describe(&chords)), Some(chords), "{}", keys);
+        }
+    }
+
+    #[test]
+    fn lookup_sequences() {
+        let mut keymap = Keymap::default_keymap();
+        keymap
+            .apply("\"Ctrl-K Ctrl-S\" = \"save\"\n\"Ctrl-Z\" = \"none\"")
+            .unwrap();
+        let ctrl = |c| key(KeyCode::Char(c), KeyModifiers::CONTROL);
+        assert!(matches!(keymap.lookup(ctrl('k')), Lookup::Pending(keys) if keys == "Ctrl-K"));
+        assert_eq!(command(keymap.lookup(ctrl('s'))), Some(Command::Save));
+        // 序列中间按下其他键, 已经按下的键都不再等待
+        assert!(matches!(keymap.lookup(ctrl('k')), Lookup::Pending(_)));
+        assert!(matches!(keymap.lookup(ctrl('q')), Lookup::Unbound(keys) if keys.len() == 2));
+        assert_eq!(command(keymap.lookup(ctrl('q'))), Some(Command::Quit));
+        assert!(matches!(keymap.lookup(ctrl('z')), Lookup::Unbound(_)));
+        assert!(keymap.apply("\"Ctrl-K\" = \"jump\"").is_err());
+    }
+
+    #[test]
+    fn default_keymap() {
+        let mut keymap = Keymap::default_keymap();
+        assert_eq!(keymap.bindings.len(), DEFAULT_KEYMAP.len());
+        // 每个命令都有默认的键
+        for (name, command) in COMMANDS {
+            assert!(!keymap.keys_of(*command).starts_with('<'), "{}", name);
+        }
+        // 终端发送的按键
+        let bindings = [
+            (
+                key(KeyCode::Char('s'), KeyModifiers::CONTROL),
+                Command::Save,
+            ),
+            (
+                key(KeyCode::Char('q'), KeyModifiers::CONTROL),
+                Command::Quit,
+            ),
+            (key(KeyCode::Char(':'), KeyModifiers::ALT), Command::Set),
+            (key(KeyCode::Tab, KeyModifiers::NONE), Command::Indent),
+            (key(KeyCode::BackTab, KeyModifiers::SHIFT), Command::Dedent),
+            (key(KeyCode::Up, KeyModifiers::SHIFT), Command::SelectUp),
+            (key(KeyCode::Enter, KeyModifiers::NONE), Command::Newline),
+            (
+                key(KeyCode::Delete, KeyModifiers::NONE),
+                Command::DeleteForward,
+            ),
+        ];
+        for (event, expected) in bindings {
+            assert_eq!(command(keymap.lookup(event)), Some(expected));
+        }
+        assert!(matches!(
+            keymap.lookup(key(KeyCode::Char('S'), KeyModifiers::SHIFT)),
+            Lookup::Unbound(_)
+        ));
+        assert_eq!(
+            keymap.help(),
+            "HELP: Ctrl-S = Save | Ctrl-Q = Quit | Ctrl-F = Find | Ctrl-R = Replace | Ctrl-O = Open \
+             | Ctrl-B = Buffers | Ctrl-Z = Undo | Ctrl-Y = Redo | Alt-: = Set"
+        );
+    }
+}
@@ -1,30 +1,56 @@
 use crossterm::style::*;
 use crossterm::{
     cursor,
-    event::KeyCode,
+    event::{Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind},
     execute, queue, style,
     terminal::{self, ClearType},
 };
-use itertools::Itertools;
-use itertools::*;
-use std::cell::RefCell;
+use ropey::{Rope, RopeBuilder};
+use std::borrow::Cow;
+use std::cell::{Ref, RefCell};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::sync::Mutex;
 use std::{
-    cmp, env, fs,
-    io::{self, stdout, ErrorKind, Write},
+    env, fs,
+    io::{self, stdout, Read, Write},
+    ops::Range,
     path::{Path, PathBuf},
-    time::{Duration, Instant},
+    time::Instant,
 };
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+use unicode_width::UnicodeWidthStr;
 
 use crate::prompt;
 
-use self::cursor_controller::CursorController;
+use super::config;
+use super::reader::Reader;
 
+use self::buffer::Buffer;
+use self::clipboard::ClipboardProvider;
+use self::cursor_controller::CursorController;
+use self::editorconfig::EditorConfig;
+use self::file_format::{FileFormat, LineEnding, BOM};
+use self::history::{Change, Edit};
+use self::indent::{Indent, IndentRules, PlainText};
+use self::search::Search;
+use self::swap::StaleSwap;
+use self::view::{Layout, LineNumbers, Rect, SplitDirection, View};
+
+pub mod buffer;
+pub mod clipboard;
 pub mod cursor_controller;
+pub mod editorconfig;
+pub mod file_format;
+pub mod history;
+pub mod indent;
+pub mod save;
+pub mod search;
+pub mod swap;
+pub mod view;
 
 static VERSION: &str = "0.1.0";
-static TAB_STOP: usize = 8;
+static READ_CHUNK: usize = 64 * 1024; // Files are read in chunks of this size.
 
 #[derive(Default)]
 pub struct Row {
@@ -32,34 +58,82 @@ pub struct Row {
     render: String,
     highlight: Vec<HighlightType>, // Save the type of each char in render of this row. So that we can render it in different color.
     is_comment: bool,
+    in_block_comment: bool, // Whether the row starts in a block comment, it's highlighted with this state.
+    tab_stop: usize,        // Width of tab in render.
 }
 
 impl Row {
-    pub fn new(row_content: String, render: String) -> Self {
+    pub fn new(row_content: String, render: String, tab_stop: usize) -> Self {
         Self {
             row_content,
             render,
             highlight: Vec::new(),
             is_comment: false,
+            in_block_comment: false,
+            tab_stop,
         }
     }
 
-    fn origin_len(&self) -> usize {
-        self.row_content.len()
-    }
-
-    fn render_len(&self) -> usize {
-        self.render.len()
-    }
-
-    fn insert_char(&mut self, at: usize, ch: char) {
-        self.row_content.insert(at, ch);
-        EditorRows::render_row(self)
+    /// The part of render which is displayed in columns `column_offset..column_offset + screen_columns`.
+    /// Returns
+    ///     usize: blank columns before the part, left by a wide char which is cut by the left edge of screen.
+    ///     Range<usize>: bytes of the part in render.
+    ///     Range<usize>: chars of the part in render, which is also the range in highlight.
+    /// Wide chars cut by the right edge of screen are not included either.
+    fn visible_render(
+        &self,
+        column_offset: usize,
+        screen_columns: usize,
+    ) -> (usize, Range<usize>, Range<usize>) {
+        let mut render_x = 0;
+        let mut chars = 0;
+        let mut start = None;
+        let mut end = (self.render.len(), self.render.chars().count());
+        for (index, grapheme) in self.render.grapheme_indices(true) {
+            let width = grapheme.width();
+            if start.is_none() && render_x >= column_offset {
+                start = Some((render_x - column_offset, index, chars));
+            }
+            if render_x + width > column_offset + screen_columns {
+                end = (index, chars);
+                break;
+            }
+            render_x += width;
+            chars += grapheme.chars().count();
+        }
+        match start {
+            Some((padding, index, chars)) => (padding, index..end.0, chars..end.1),
+            None => (0, 0..0, 0..0),
+        }
     }
 
-    fn delete_char(&mut self, at: usize) {
-        self.row_content.remove(at);
-        EditorRows::render_row(self)
+    /// Where the row is broken into lines when it's wrapped at `width` columns.
+    /// Returns the render x at which each line starts, the first line starts at 0.
+    /// With `word`, lines are broken after a whitespace if there is one in the line.
+    /// If the last line is full, an empty line is added for the cursor at the end of row.
+    fn wrap_points(&self, width: usize, word: bool) -> Vec<usize> {
+        let mut points = vec![0];
+        let mut line_start = 0;
+        let mut word_break = None; // render x after the last whitespace in the line.
+        let mut render_x = 0;
+        for grapheme in self.render.graphemes(true) {
+            let grapheme_width = grapheme.width();
+            if render_x + grapheme_width > line_start + width && render_x > line_start {
+                line_start = match word_break {
+                    Some(at) if word && at > line_start => at,
+                    _ => render_x,
+                };
+                points.push(line_start);
+            }
+            render_x += grapheme_width;
+            if grapheme.chars().all(char::is_whitespace) {
+                word_break = Some(render_x);
+            }
+        }
+        if render_x > line_start && render_x - line_start >= width {
+            points.push(render_x);
+        }
+        points
     }
 
     /// args
@@ -68,19 +142,143 @@ impl Row {
     ///     usize: x position of cursor in row_content of not being rendered
     fn get_row_content_x(&self, render_x: usize) -> usize {
         let mut current_render_x = 0;
-        for (cursor_x, ch) in self.row_content.chars().enumerate() {
-            if ch == '\t' {
-                current_render_x += (TAB_STOP - 1) - (current_render_x % TAB_STOP);
-            }
-            current_render_x += 1;
+        for (cursor_x, grapheme) in self.row_content.grapheme_indices(true) {
+            current_render_x += grapheme_width(grapheme, current_render_x, self.tab_stop);
             if current_render_x > render_x {
                 return cursor_x;
             }
         }
-        0
+        self.row_content.len()
+    }
+
+    /// args
+    ///     usize: x position of cursor in row_content, it must be at a grapheme boundary.
+    /// Returns
+    ///     usize: x position of cursor in rendered row, which is the display width of chars before it.
+    fn get_render_x(&self, cursor_x: usize) -> usize {
+        self.row_content[..cursor_x]
+            .graphemes(true)
+            .fold(0, |render_x, grapheme| {
+                render_x + grapheme_width(grapheme, render_x, self.tab_stop)
+            })
+    }
+
+    /// Start of the grapheme before `cursor_x`.
+    fn previous_boundary(&self, cursor_x: usize) -> usize {
+        self.row_content[..cursor_x]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    /// End of the grapheme after `cursor_x`.
+    fn next_boundary(&self, cursor_x: usize) -> usize {
+        self.row_content[cursor_x..]
+            .graphemes(true)
+            .next()
+            .map_or(cursor_x, |grapheme| cursor_x + grapheme.len())
+    }
+
+    /// Byte index in render of the grapheme at `render_x`.
+    fn render_index(&self, render_x: usize) -> usize {
+        let mut current_render_x = 0;
+        for (index, grapheme) in self.render.grapheme_indices(true) {
+            if current_render_x >= render_x {
+                return index;
+            }
+            current_render_x += grapheme.width();
+        }
+        self.render.len()
+    }
+
+    /// Index of char in render which is displayed at x position `cursor_x` of row_content.
+    fn render_char_index(&self, cursor_x: usize) -> usize {
+        let index = self.render_index(self.get_render_x(cursor_x));
+        self.render[..index].chars().count()
+    }
+
+    /// Chars of render which are selected, if row `at` is in the selection.
+    fn selected_chars(
+        &self,
+        at: usize,
+        (start, end): ((usize, usize), (usize, usize)),
+    ) -> Option<Range<usize>> {
+        if at < start.1 || at > end.1 {
+            return None;
+        }
+        let start_x = if at == start.1 { start.0 } else { 0 };
+        let end_x = if at == end.1 {
+            end.0
+        } else {
+            self.row_content.len()
+        };
+        Some(self.render_char_index(start_x)..self.render_char_index(end_x))
     }
 }
 
+/// Position right after `text` when it's inserted at `at`.
+fn text_end((x, y): (usize, usize), text: &str) -> (usize, usize) {
+    match text.rfind('\n') {
+        Some(index) => (text.len() - index - 1, y + text.matches('\n').count()),
+        None => (x + text.len(), y),
+    }
+}
+
+/// Display width of a grapheme which is displayed at `render_x`.
+fn grapheme_width(grapheme: &str, render_x: usize, tab_stop: usize) -> usize {
+    if grapheme == "\t" {
+        tab_stop - render_x % tab_stop
+    } else if caret_notation(grapheme).is_some() {
+        2
+    } else {
+        grapheme.width()
+    }
+}
+
+/// The longest prefix of `s` which fits in `width` columns, graphemes are never split.
+fn truncate_to_width(s: &str, width: usize) -> &str {
+    let mut current_width = 0;
+    for (index, grapheme) in s.grapheme_indices(true) {
+        current_width += grapheme.width();
+        if current_width > width {
+            return &s[..index];
+        }
+    }
+    s
+}
+
+/// Control chars are shown like "^M", so that they don't move the cursor of terminal.
+fn caret_notation(grapheme: &str) -> Option<[char; 2]> {
+    match grapheme.as_bytes() {
+        [c @ (0..=0x1F | 0x7F)] => Some(['^', (c ^ 0x40) as char]),
+        _ => None,
+    }
+}
+
+/// Length of the char which is cut at the end of `bytes`, 0 if the last char is complete.
+fn incomplete_char_len(bytes: &[u8]) -> usize {
+    for (back, byte) in bytes.iter().rev().take(4).enumerate() {
+        // 跳过后续字节 (10xxxxxx), 找到字符的第一个字节
+        if byte & 0xC0 == 0x80 {
+            continue;
+        }
+        let len = match byte {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 1,
+        };
+        return if len > back + 1 { back + 1 } else { 0 };
+    }
+    0
+}
+
+fn is_grapheme_boundary(s: &str, index: usize) -> bool {
+    GraphemeCursor::new(index, s.len(), true)
+        .is_boundary(s, 0)
+        .unwrap_or(false)
+}
+
 pub struct StatusMessage {
     message: Option<String>,
     set_time: Option<Instant>,
@@ -101,7 +299,7 @@ impl StatusMessage {
 
     fn message(&mut self) -> Option<&String> {
         self.set_time.and_then(|time| {
-            if time.elapsed() > Duration::from_secs(5) {
+            if time.elapsed() > config::get().message_timeout {
                 self.message = None;
                 self.set_time = None;
                 None
@@ -117,6 +315,12 @@ pub struct EditorContents {
     content: String,
 }
 
+impl Default for EditorContents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl EditorContents {
     pub fn new() -> Self {
         EditorContents {
@@ -158,117 +362,466 @@ impl Write for EditorContents {
 /// 2. Write file
 /// 3. Edit file
 /// 4. Render file
+///
+/// The text is kept in a rope, so editing a row never moves the other rows.
+/// Rows are only rendered when they are needed, and cached in `row_cache`.
+/// With syntax highlight, only whether each row ends in a block comment is kept for all rows before the screen.
 pub struct EditorRows {
-    pub row_contents: Vec<Row>, // Box<str> and String are same, but Box<str> is more efficient and smaller.
+    text: Rope, // Every row is terminated by '\n', so the number of rows is `len_lines() - 1`.
+    row_cache: RefCell<BTreeMap<usize, Row>>, // Rendered rows, key is index of row.
+    comment_states: RefCell<Vec<bool>>, // Whether row ends in a block comment, known for the first rows.
+    pub syntax_highlight: Option<Box<dyn SyntaxHighlight>>,
     pub filename: Option<PathBuf>, //add field
+    pub(crate) file_format: FileFormat,
+    pub(crate) indent: Option<Indent>, // Detected from the file, the setting is used if it's None.
+    tab_stop: Option<usize>,           // From `.editorconfig`, the setting is used if it's None.
+    pub(crate) trim_trailing_whitespace: bool, // Trailing whitespace is removed before saving.
+    stale_swap: Option<StaleSwap>, // Swap file left by another editor, found when the file is opened.
+    pub read_only: bool, // The file can't be saved as it is, e.g. invalid UTF-8 has been replaced.
+    open_message: Option<String>, // Error or notice about opening the file, shown in status bar.
 }
 
 impl EditorRows {
-    fn new(syntax_highlight: &mut Option<Box<dyn SyntaxHighlight>>) -> Self {
-        match env::args().nth(1) {
-            None => Self {
-                row_contents: Vec::new(),
-                filename: None,
-            },
-            Some(file) => Self::from_file(file.into(), syntax_highlight),
+    /// Empty buffer, it's saved to `filename`.
+    pub(crate) fn empty(filename: Option<PathBuf>) -> Self {
+        let syntax_highlight = filename
+            .as_ref()
+            .and_then(|file| file.extension())
+            .and_then(|ext| ext.to_str()) // 使用 and_then() 而不是 map(), 因为 ext.to_str() 返回的是 Option
+            .and_then(Output::select_syntax);
+        Self {
+            text: Rope::new(),
+            row_cache: RefCell::new(BTreeMap::new()),
+            comment_states: RefCell::new(Vec::new()),
+            syntax_highlight,
+            filename,
+            file_format: FileFormat::default(),
+            indent: None,
+            tab_stop: None,
+            trim_trailing_whitespace: false,
+            stale_swap: None,
+            read_only: false,
+            open_message: None,
         }
     }
 
-    fn from_file(file: PathBuf, syntax_highlight: &mut Option<Box<dyn SyntaxHighlight>>) -> Self {
-        let file_contents = fs::read_to_string(&file).expect("Unable to read file");
-
-        file.extension()
-            .and_then(|ext| ext.to_str()) // 使用 and_then() 而不是 map(), 因为 ext.to_str() 返回的是 Option
-            .map(|ext| Output::select_syntax(ext).map(|syntax| syntax_highlight.insert(syntax)));
-
-        let mut content: Vec<Row> = Vec::new();
-
-        file_contents.lines().enumerate().for_each(|(index, line)| {
-            let mut row = Row::new(line.to_string(), String::new());
-            Self::render_row(&mut row);
-            content.push(row);
-            if let Some(s) = syntax_highlight {
-                s.update_syntax(index, &mut content);
+    /// Open `file`, errors never stop the editor, they are shown in `open_message`:
+    /// - A file which doesn't exist is a new empty buffer, it's created when it's saved.
+    /// - Invalid UTF-8 is replaced by U+FFFD, and the buffer is read-only so that the file isn't damaged by saving.
+    /// - Directories and files which can't be read give an empty buffer without file name.
+    pub(crate) fn from_file(file: PathBuf) -> Self {
+        if file.is_dir() {
+            let mut editor_rows = Self::empty(None);
+            editor_rows.open_message = Some(format!("{} is a directory", file.display()));
+            return editor_rows;
+        }
+        let result = match Self::read_text(&file, false) {
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => Self::read_text(&file, true)
+                .map(|(text, file_format, mixed)| (text, file_format, mixed, true)),
+            result => result.map(|(text, file_format, mixed)| (text, file_format, mixed, false)),
+        };
+        let (text, file_format, mixed, lossy) = match result {
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let mut editor_rows = Self::empty(Some(file));
+                editor_rows.apply_editorconfig();
+                // 从未保存过的文件也可能留下交换文件
+                editor_rows.stale_swap = editor_rows.filename.as_deref().and_then(swap::find_stale);
+                editor_rows.open_message = Some("New file".into());
+                return editor_rows;
             }
-        });
+            Err(err) => {
+                let mut editor_rows = Self::empty(None);
+                editor_rows.open_message = Some(format!("Can't open {}: {}", file.display(), err));
+                return editor_rows;
+            }
+        };
 
         /* modify */
-        Self {
-            row_contents: content,
-            filename: Some(file),
+        let mut editor_rows = Self::empty(Some(file));
+        editor_rows.indent = indent::detect(&text);
+        editor_rows.text = text;
+        editor_rows.file_format = file_format;
+        editor_rows.apply_editorconfig();
+        editor_rows.stale_swap = editor_rows.filename.as_deref().and_then(swap::find_stale);
+        if lossy {
+            editor_rows.read_only = true;
+            editor_rows.open_message = Some(
+                "File is not valid UTF-8, it's opened read-only. Ctrl-S saves it as another file"
+                    .into(),
+            );
+        } else if mixed {
+            editor_rows.open_message = Some(format!(
+                "File has mixed line endings, they will be saved as {}",
+                editor_rows.file_format.line_ending.name()
+            ));
         }
+        editor_rows
         /* end */
     }
 
-    /* add functions*/
-    fn get_render(&self, at: usize) -> &String {
-        &self.row_contents[at].render
+    /// Read file into a rope in chunks, "\r\n" is converted to "\n" and the BOM is removed.
+    /// Returns the format of the file as well: the line break used by most rows, final line break and BOM,
+    /// and whether both "\n" and "\r\n" are used.
+    /// Invalid UTF-8 is an error of `InvalidData`, unless it's replaced by U+FFFD when `lossy` is true.
+    fn read_text(file: &Path, lossy: bool) -> io::Result<(Rope, FileFormat, bool)> {
+        let mut file = File::open(file)?;
+        let mut builder = RopeBuilder::new();
+        let mut chunk = vec![0; READ_CHUNK];
+        let mut pending = Vec::new(); // Bytes which haven't been appended to the rope.
+        let mut bom = None;
+        let mut terminated = true;
+        let (mut lf, mut crlf) = (0, 0);
+        loop {
+            let len = match file.read(&mut chunk) {
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            let eof = len == 0;
+            pending.extend_from_slice(&chunk[..len]);
+            if bom.is_none() {
+                if pending.len() < BOM.len() && !eof {
+                    continue;
+                }
+                bom = Some(pending.starts_with(BOM.as_bytes()));
+                if bom == Some(true) {
+                    pending.drain(..BOM.len());
+                }
+            }
+            // 块的末尾可能是 "\r\n" 的前一半, 或者一个字符的一部分, 留给下一块
+            let mut end = pending.len();
+            if !eof {
+                if pending.ends_with(b"\r") {
+                    end -= 1;
+                }
+                end -= incomplete_char_len(&pending[..end]);
+            }
+            let text = match std::str::from_utf8(&pending[..end]) {
+                Ok(text) => Cow::Borrowed(text),
+                Err(_) if lossy => String::from_utf8_lossy(&pending[..end]),
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            };
+            if !text.is_empty() {
+                let newlines = text.matches('\n').count();
+                let crlfs = text.matches("\r\n").count();
+                (lf, crlf) = (lf + newlines - crlfs, crlf + crlfs);
+                terminated = text.ends_with('\n');
+                if crlfs > 0 {
+                    builder.append(&text.replace("\r\n", "\n"));
+                } else {
+                    builder.append(&text);
+                }
+            }
+            pending.drain(..end);
+            if eof {
+                break;
+            }
+        }
+        if !terminated {
+            builder.append("\n");
+        }
+        let file_format = FileFormat {
+            line_ending: if crlf > lf {
+                LineEnding::Crlf
+            } else {
+                LineEnding::Lf
+            },
+            final_newline: terminated,
+            bom: bom == Some(true),
+        };
+        Ok((builder.finish(), file_format, lf > 0 && crlf > 0))
+    }
+
+    pub fn set_syntax_highlight(&mut self, syntax_highlight: Box<dyn SyntaxHighlight>) {
+        self.syntax_highlight = Some(syntax_highlight);
+        self.clear_cache();
+    }
+
+    /// Indentation of the buffer.
+    pub(crate) fn indent(&self) -> Indent {
+        self.indent.unwrap_or(config::get().indent)
+    }
+
+    /// Width of tab.
+    pub(crate) fn tab_stop(&self) -> usize {
+        self.tab_stop.unwrap_or(config::get().tab_stop)
+    }
+
+    /// Apply the `.editorconfig` files of the file, they override the detected indentation and format of the file.
+    pub(crate) fn apply_editorconfig(&mut self) {
+        let Some(file) = &self.filename else {
+            return;
+        };
+        let editor_config = EditorConfig::find(file);
+        self.tab_stop = editor_config.tab_width();
+        self.indent = editor_config.indent(self.indent()).or(self.indent);
+        if let Some(line_ending) = editor_config.line_ending() {
+            self.file_format.line_ending = line_ending;
+        }
+        if let Some(bom) = editor_config.bom() {
+            self.file_format.bom = bom;
+        }
+        if let Some(final_newline) = editor_config.insert_final_newline() {
+            self.file_format.final_newline = final_newline;
+        }
+        self.trim_trailing_whitespace = editor_config.trim_trailing_whitespace() == Some(true);
+        self.clear_cache();
+    }
+
+    /// How rows are indented in the language of the buffer.
+    fn indent_rules(&self) -> &dyn IndentRules {
+        match &self.syntax_highlight {
+            Some(it) => it.as_ref(),
+            None => &PlainText,
+        }
+    }
+
+    /// Drop all rendered rows, e.g. the way of rendering has changed.
+    fn clear_cache(&mut self) {
+        self.row_cache.get_mut().clear();
+        self.comment_states.get_mut().clear();
     }
 
     pub fn render_row(row: &mut Row) {
-        let mut index = 0;
+        let mut render_x = 0;
         let capacity = row
             .row_content
             .chars()
             //modify
-            .fold(0, |acc, next| acc + if next == '\t' { TAB_STOP } else { 1 });
-        row.render = String::with_capacity(capacity);
-        row.row_content.chars().for_each(|c| {
-            index += 1;
-            if c == '\t' {
-                row.render.push(' ');
-                while index % TAB_STOP != 0 {
-                    // modify
-                    row.render.push(' ');
-                    index += 1
+            .fold(0, |acc, next| {
+                acc + if next == '\t' {
+                    row.tab_stop
+                } else {
+                    next.len_utf8()
                 }
+            });
+        row.render = String::with_capacity(capacity);
+        row.row_content.graphemes(true).for_each(|grapheme| {
+            let width = grapheme_width(grapheme, render_x, row.tab_stop);
+            if grapheme == "\t" {
+                // modify
+                (0..width).for_each(|_| row.render.push(' '));
+            } else if let Some(caret) = caret_notation(grapheme) {
+                row.render.extend(caret);
             } else {
-                row.render.push(c);
+                row.render.push_str(grapheme);
             }
+            render_x += width;
         });
     }
 
     pub fn number_of_rows(&self) -> usize {
-        self.row_contents.len() /* modify */
+        self.text.len_lines() - 1 /* modify */
+    }
+
+    /// Content of row without the line break.
+    pub fn row_content(&self, at: usize) -> Cow<'_, str> {
+        let line = self.text.line(at);
+        line.slice(..line.len_chars() - 1).into()
+    }
+
+    /// Length of row in bytes, without the line break.
+    pub fn row_len(&self, at: usize) -> usize {
+        self.text.line(at).len_bytes() - 1
     }
 
-    fn get_editor_row(&self, at: usize) -> &Row {
-        &self.row_contents[at] /* modify */
+    fn get_editor_row(&self, at: usize) -> Ref<'_, Row> {
+        self.render_rows_until(at);
+        Ref::map(self.row_cache.borrow(), |cache| &cache[&at]) /* modify */
+    }
+
+    /// Render row `at` into the cache if it isn't there yet.
+    /// With syntax highlight, a row depends on whether the previous row ends in a block comment.
+    /// The states of rows before it are worked out first, the rows rendered for that aren't cached.
+    fn render_rows_until(&self, at: usize) {
+        let mut cache = self.row_cache.borrow_mut();
+        let Some(syntax_highlight) = self.syntax_highlight.as_ref() else {
+            cache.entry(at).or_insert_with(|| self.render(at, None));
+            return;
+        };
+        let mut states = self.comment_states.borrow_mut();
+        for i in states.len().min(at)..=at {
+            let in_block_comment = i > 0 && states[i - 1];
+            let is_comment = match cache.get(&i) {
+                Some(row) if row.in_block_comment == in_block_comment => row.is_comment,
+                _ => {
+                    let row = self.render(i, Some((syntax_highlight.as_ref(), in_block_comment)));
+                    let is_comment = row.is_comment;
+                    // 缓存中已有的行用新的状态重新高亮
+                    if i == at || cache.contains_key(&i) {
+                        cache.insert(i, row);
+                    }
+                    is_comment
+                }
+            };
+            if i == states.len() {
+                states.push(is_comment);
+            }
+        }
+    }
+
+    /// Render row `at`, which is highlighted if `highlight` gives the state of block comment before it.
+    fn render(&self, at: usize, highlight: Option<(&dyn SyntaxHighlight, bool)>) -> Row {
+        let mut row = Row::new(
+            self.row_content(at).into_owned(),
+            String::new(),
+            self.tab_stop(),
+        );
+        Self::render_row(&mut row);
+        if let Some((syntax_highlight, in_block_comment)) = highlight {
+            syntax_highlight.update_syntax(&mut row, in_block_comment);
+            row.in_block_comment = in_block_comment;
+        }
+        row
+    }
+
+    /// Rows in `rows` have been replaced by `new_len` rows, drop them from the cache.
+    /// The following rows are moved to their new index, highlighted rows are highlighted again
+    /// when they are used if the state of block comment before them has changed.
+    fn invalidate_rows(&mut self, rows: Range<usize>, new_len: usize) {
+        let cache = self.row_cache.get_mut();
+        let tail = cache.split_off(&rows.start);
+        cache.extend(
+            tail.into_iter()
+                .filter(|(i, _)| *i >= rows.end)
+                .map(|(i, row)| (i - rows.end + rows.start + new_len, row)),
+        );
+        self.comment_states.get_mut().truncate(rows.start);
+    }
+
+    /// Drop the cached rows which are far away from the screen.
+    fn shrink_cache(&mut self, screen: Range<usize>) {
+        let margin = screen.len();
+        let keep = screen.start.saturating_sub(margin)..screen.end + margin;
+        self.row_cache.get_mut().retain(|i, _| keep.contains(i));
+    }
+
+    /// Position (x, y) after the last row is moved to the end of the last row,
+    /// x beyond the end of its row is moved back into the row.
+    fn clamp_position(&self, (x, y): (usize, usize)) -> (usize, usize) {
+        let number_of_rows = self.number_of_rows();
+        if y < number_of_rows {
+            self.snap_position((x, y))
+        } else if number_of_rows == 0 {
+            (0, 0)
+        } else {
+            (self.row_len(number_of_rows - 1), number_of_rows - 1)
+        }
+    }
+
+    /// Move position (x, y) back into the text, onto a grapheme boundary at or before it.
+    pub(crate) fn snap_position(&self, (x, y): (usize, usize)) -> (usize, usize) {
+        let number_of_rows = self.number_of_rows();
+        if y >= number_of_rows {
+            return (0, number_of_rows);
+        }
+        // 光标必须落在字形 (grapheme) 的边界上
+        let row = self.get_editor_row(y);
+        let x = row
+            .row_content
+            .grapheme_indices(true)
+            .map(|(i, _)| i)
+            .chain([row.row_content.len()])
+            .take_while(|&i| i <= x)
+            .last()
+            .unwrap_or(0);
+        (x, y)
+    }
+
+    /// Text between positions (x, y), rows are separated by '\n'.
+    fn text_between(&self, start: (usize, usize), end: (usize, usize)) -> String {
+        self.text
+            .slice(self.char_index(start.0, start.1)..self.char_index(end.0, end.1))
+            .to_string()
+    }
+
+    /// Index of char in rope at (x, y).
+    fn char_index(&self, x: usize, y: usize) -> usize {
+        self.text.line_to_char(y) + self.text.line(y).byte_to_char(x)
     }
 
     fn insert_row(&mut self, at: usize, contents: String) {
-        let mut new_row = Row::new(contents, String::new());
-        EditorRows::render_row(&mut new_row);
-        self.row_contents.insert(at, new_row);
+        let index = self.text.line_to_char(at);
+        self.text.insert(index, "\n");
+        self.text.insert(index, &contents);
+        self.invalidate_rows(at..at, 1);
     }
 
-    fn get_editor_row_mut(&mut self, at: usize) -> &mut Row {
-        &mut self.row_contents[at]
+    fn remove_row(&mut self, at: usize) {
+        let index = self.text.line_to_char(at);
+        self.text.remove(index..self.text.line_to_char(at + 1));
+        self.invalidate_rows(at..at + 1, 0);
     }
 
-    pub fn save(&self) -> io::Result<usize> {
+    /// Apply an edit to rows.
+    pub(crate) fn apply_edit(&mut self, edit: &Edit) {
+        match edit {
+            Edit::Insert { at: (x, y), text } => self.insert_text(*x, *y, text),
+            Edit::Delete { at: (x, y), text } => self.delete_text(*x, *y, text),
+            Edit::InsertRow { at } => self.insert_row(*at, String::new()),
+            Edit::DeleteRow { at } => self.remove_row(*at),
+            Edit::LineEnding { to, .. } => self.file_format.line_ending = *to,
+        }
+    }
+
+    /// Insert text at (x, y), every '\n' in text splits the row.
+    fn insert_text(&mut self, x: usize, y: usize, text: &str) {
+        let newlines = text.matches('\n').count();
+        self.text.insert(self.char_index(x, y), text);
+        self.invalidate_rows(y..y + 1, newlines + 1);
+    }
+
+    /// Delete text at (x, y), every '\n' in text joins the next row.
+    fn delete_text(&mut self, x: usize, y: usize, text: &str) {
+        let newlines = text.matches('\n').count();
+        (0..newlines).for_each(|_| self.join_adjacent_rows(y + 1));
+        let start = self.char_index(x, y);
+        self.text
+            .remove(start..start + text.chars().count() - newlines);
+        self.invalidate_rows(y..y + 1, 1);
+    }
+
+    /// Write rows to the file in its original format: line breaks, final line break and BOM.
+    /// The file is replaced atomically, see `save::write_file`.
+    pub fn save(&self) -> io::Result<save::Written> {
         match &self.filename {
-            None => Err(io::Error::new(ErrorKind::Other, "no file name specified")),
-            Some(name) => {
-                let mut file = fs::OpenOptions::new().write(true).create(true).open(name)?;
-                let contents: String = self
-                    .row_contents
-                    .iter()
-                    .map(|it| it.row_content.as_str())
-                    .collect::<Vec<&str>>()
-                    .join("\n");
-                file.set_len(contents.len() as u64)?;
-                file.write_all(contents.as_bytes())?;
-                Ok(contents.as_bytes().len())
+            None => Err(io::Error::other("no file name specified")),
+            Some(name) => save::write_file(name, config::get().backup, |writer| {
+                self.write_contents(writer)
+            }),
+        }
+    }
+
+    fn write_contents(&self, writer: &mut dyn Write) -> io::Result<usize> {
+        let mut written = 0;
+        if self.file_format.bom && self.text.len_chars() > 0 {
+            writer.write_all(BOM.as_bytes())?;
+            written += BOM.len();
+        }
+        let contents = if self.file_format.final_newline {
+            self.text.slice(..)
+        } else {
+            self.text.slice(..self.text.len_chars().saturating_sub(1))
+        };
+        let line_ending = self.file_format.line_ending.as_str();
+        for chunk in contents.chunks() {
+            for (i, part) in chunk.split('\n').enumerate() {
+                if i > 0 {
+                    writer.write_all(line_ending.as_bytes())?;
+                    written += line_ending.len();
+                }
+                writer.write_all(part.as_bytes())?;
+                written += part.len();
             }
         }
+        Ok(written)
     }
 
     fn join_adjacent_rows(&mut self, at: usize) {
-        let current_row = self.row_contents.remove(at);
-        let previous_row = self.get_editor_row_mut(at - 1);
-        previous_row.row_content.push_str(&current_row.row_content);
-        Self::render_row(previous_row);
+        let index = self.text.line_to_char(at) - 1;
+        self.text.remove(index..index + 1);
+        self.invalidate_rows(at - 1..at + 1, 1);
     }
 }
 
@@ -279,84 +832,664 @@ impl EditorRows {
 /// 3. Write to stdout
 /// 4. Move cursor
 pub(crate) struct Output {
-    pub win_size: (usize, usize),
+    screen_size: (usize, usize),     // Size of terminal.
+    pub win_size: (usize, usize),    // Size of the area for rows in the focused view.
     editor_contents: EditorContents, // #TODO 这里可以直接用 Stdout 吗?
-    pub cursor_controller: CursorController,
-    pub editor_rows: EditorRows,
+    pub buffer: Buffer,              // The buffer being edited.
+    buffers: Vec<Buffer>, // The other buffers, the current one is taken out of the list at index `current`.
+    current: usize,
+    layout: Layout,
+    focus: usize, // Index of the view being edited, whose buffer is `buffer`.
     pub status_message: StatusMessage,
-    pub dirty: u64,
-    pub syntax_highlight: Option<Box<dyn SyntaxHighlight>>,
-    previous_highlight: Option<(usize, Vec<HighlightType>)>,
+    search: Search,
+    clipboard: Box<dyn ClipboardProvider>,
 }
 
-impl Output {
-    pub fn new() -> Self {
-        let win_size = terminal::size()
-            .map(|(x, y)| (x as usize, y as usize - 2))
-            .unwrap();
-        let mut syntax_highlight: Option<Box<dyn SyntaxHighlight>> = None;
-        Self {
-            win_size,
-            editor_contents: EditorContents::new(),
-            cursor_controller: CursorController::new(win_size),
-            editor_rows: EditorRows::new(&mut syntax_highlight),
-            status_message: StatusMessage::new(
-                "HELP: Ctrl-S = Save | Ctrl-Q = Quit | Ctrl-F = Find ".into(),
+impl Output {
+    /// `help` is shown in status bar, unless there is a message about opening the file.
+    pub fn new(help: String) -> Self {
+        let screen_size = terminal::size()
+            .map(|(x, y)| (x as usize, y as usize))
+            .unwrap();
+        let win_size = Self::view_size(Self::views_area(screen_size), 0);
+        // 命令行参数中的每个文件都在一个缓冲区中打开
+        let mut buffers: Vec<Buffer> = env::args()
+            .skip(1)
+            .map(|file| Buffer::new(EditorRows::from_file(file.into()), win_size))
+            .collect();
+        if buffers.is_empty() {
+            buffers.push(Buffer::new(EditorRows::empty(None), win_size));
+        }
+        let mut buffer = buffers.remove(0);
+        let layout = Layout::View(View {
+            buffer: 0,
+            cursor_controller: buffer.cursor_controller,
+        });
+        Self {
+            screen_size,
+            win_size,
+            editor_contents: EditorContents::new(),
+            status_message: StatusMessage::new(
+                buffer.editor_rows.open_message.take().unwrap_or(help),
+            ),
+            buffer,
+            buffers,
+            current: 0,
+            layout,
+            focus: 0,
+            search: Search::default(),
+            clipboard: clipboard::detect(),
+        }
+    }
+
+    fn buffer_count(&self) -> usize {
+        self.buffers.len() + 1
+    }
+
+    /// All buffers in the order of buffer list.
+    fn all_buffers(&self) -> impl Iterator<Item = &Buffer> {
+        let (before, after) = self.buffers.split_at(self.current);
+        before
+            .iter()
+            .chain(std::iter::once(&self.buffer))
+            .chain(after)
+    }
+
+    /// Number of buffers which have unsaved changes.
+    pub fn dirty_buffers(&self) -> usize {
+        self.all_buffers().filter(|it| it.dirty > 0).count()
+    }
+
+    /// Buffer `index` of buffer list.
+    fn buffer_at(&self, index: usize) -> &Buffer {
+        self.all_buffers().nth(index).unwrap()
+    }
+
+    /// Edit buffer `target` of buffer list in the focused view.
+    fn switch_buffer(&mut self, target: usize) {
+        if target == self.current || target >= self.buffer_count() {
+            return;
+        }
+        self.swap_buffer(target);
+        self.layout.views_mut()[self.focus].buffer = target;
+        let message = self
+            .buffer
+            .editor_rows
+            .open_message
+            .take()
+            .unwrap_or_else(|| {
+                format!(
+                    "Buffer {}/{}: {}",
+                    self.current + 1,
+                    self.buffer_count(),
+                    self.buffer.name()
+                )
+            });
+        self.status_message.set_message(message);
+    }
+
+    /// Make buffer `target` the current buffer, the current one is put back into buffer list.
+    fn swap_buffer(&mut self, target: usize) {
+        if target == self.current {
+            return;
+        }
+        self.buffer.history.seal();
+        let mut buffer = self.buffers.remove(if target < self.current {
+            target
+        } else {
+            target - 1
+        });
+        buffer
+            .cursor_controller
+            .resize(self.win_size, &buffer.editor_rows);
+        std::mem::swap(&mut self.buffer, &mut buffer);
+        self.buffers.insert(
+            if self.current < target {
+                self.current
+            } else {
+                self.current - 1
+            },
+            buffer,
+        );
+        self.current = target;
+    }
+
+    pub fn next_buffer(&mut self) {
+        if self.buffer_count() == 1 {
+            self.status_message.set_message("Only one buffer".into());
+        }
+        self.switch_buffer((self.current + 1) % self.buffer_count());
+    }
+
+    pub fn previous_buffer(&mut self) {
+        if self.buffer_count() == 1 {
+            self.status_message.set_message("Only one buffer".into());
+        }
+        self.switch_buffer((self.current + self.buffer_count() - 1) % self.buffer_count());
+    }
+
+    /// Open a file in a new buffer, or switch to its buffer if it's already open.
+    pub fn open_file(&mut self) -> io::Result<()> {
+        let Some(file) = prompt!(self, "Open: {}").filter(|it| !it.is_empty()) else {
+            return Ok(());
+        };
+        let file = PathBuf::from(file);
+        let same_file = |other: &Path| match (fs::canonicalize(&file), fs::canonicalize(other)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => file == other,
+        };
+        let opened = self.all_buffers().position(|buffer| {
+            buffer
+                .editor_rows
+                .filename
+                .as_deref()
+                .is_some_and(same_file)
+        });
+        match opened {
+            Some(index) => self.switch_buffer(index),
+            None => {
+                self.buffers
+                    .push(Buffer::new(EditorRows::from_file(file), self.win_size));
+                self.switch_buffer(self.buffer_count() - 1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Buffers whose number or name matches `input`, in the form of "1:name*" where '*' means modified.
+    fn matched_buffers(&self, input: &str) -> Vec<(usize, String)> {
+        let number = input.parse::<usize>().ok();
+        self.all_buffers()
+            .enumerate()
+            .filter(|(i, buffer)| match number {
+                Some(number) => number == i + 1,
+                None => buffer.name().contains(input),
+            })
+            .map(|(i, buffer)| {
+                let modified = if buffer.dirty > 0 { "*" } else { "" };
+                (i, format!("{}:{}{}", i + 1, buffer.name(), modified))
+            })
+            .collect()
+    }
+
+    /// Pick a buffer by its number or a part of its name, the matched buffers are listed after the prompt.
+    pub fn pick_buffer(&mut self) -> io::Result<()> {
+        let list = |output: &mut Output, input: &str, _| {
+            output
+                .matched_buffers(input)
+                .into_iter()
+                .map(|(_, name)| name)
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        self.status_message.set_message(String::new());
+        let input = prompt!(self, "Buffer: {} (number or name)", callback = list);
+        if let Some(input) = input {
+            match self.matched_buffers(&input).first() {
+                Some((index, _)) => self.switch_buffer(*index),
+                None => self
+                    .status_message
+                    .set_message(format!("No buffer matches {}", input)),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn select_syntax(extension: &str) -> Option<Box<dyn SyntaxHighlight>> {
+        let list: Vec<Box<dyn SyntaxHighlight>> = vec![Box::new(RustHighlight::new())];
+        // list.push(other highlight);
+        list.into_iter()
+            .find(|it| it.extensions().contains(&extension))
+    }
+
+    /// The area for views, the last line of screen is taken by message bar.
+    fn views_area((columns, rows): (usize, usize)) -> Rect {
+        Rect {
+            x: 0,
+            y: 0,
+            width: columns,
+            height: rows.saturating_sub(1),
+        }
+    }
+
+    /// Size of the area for rows in a view, the last line is taken by its status bar
+    /// and the first `gutter` columns by line numbers.
+    fn view_size(rect: Rect, gutter: usize) -> (usize, usize) {
+        (
+            (rect.width - gutter).max(1),
+            rect.height.saturating_sub(1).max(1),
+        )
+    }
+
+    /// Width of line numbers in a view, which is wide enough for the last row of the buffer, plus a space.
+    /// Line numbers are hidden if the view is too narrow for them.
+    fn gutter_width(&self, rect: Rect, editor_rows: &EditorRows) -> usize {
+        if let LineNumbers::Off = config::get().line_numbers {
+            return 0;
+        }
+        let width = editor_rows.number_of_rows().max(1).to_string().len() + 1;
+        if width < rect.width {
+            width
+        } else {
+            0
+        }
+    }
+
+    /// Switch soft wrap between off, wrapping at the edge of screen and wrapping at word boundaries.
+    pub fn toggle_wrap(&mut self) {
+        let wrap = config::update(|config| {
+            config.wrap = config.wrap.next();
+            config.wrap
+        });
+        self.status_message
+            .set_message(format!("Soft wrap: {}", wrap.name()));
+        self.update_layout();
+    }
+
+    /// Switch line numbers between off, absolute and relative.
+    pub fn toggle_line_numbers(&mut self) {
+        let line_numbers = config::update(|config| {
+            config.line_numbers = config.line_numbers.next();
+            config.line_numbers
+        });
+        self.status_message
+            .set_message(format!("Line numbers: {}", line_numbers.name()));
+        self.update_layout();
+    }
+
+    /// Change a setting, e.g. `:set tab_stop=4`.
+    pub fn set_option(&mut self) -> io::Result<()> {
+        let Some(command) = prompt!(self, ":set {}") else {
+            return Ok(());
+        };
+        let before = config::get();
+        let (tab_stop, indent) = (before.tab_stop, before.indent);
+        match config::set(&command) {
+            Ok(message) => self.status_message.set_message(message),
+            Err(err) => self.status_message.set_message(err),
+        }
+        // 已经渲染的行中 tab 的宽度已经过时了, 设置的宽度也用于当前的缓冲区
+        if config::get().tab_stop != tab_stop {
+            self.buffer.editor_rows.tab_stop = None;
+            self.buffer.editor_rows.clear_cache();
+            self.buffers
+                .iter_mut()
+                .for_each(|buffer| buffer.editor_rows.clear_cache());
+        }
+        // 设置的缩进也用于当前的缓冲区, 而不是从文件中检测到的缩进
+        if config::get().indent != indent {
+            self.buffer.editor_rows.indent = None;
+        }
+        if config::get().copy_command != before.copy_command
+            || config::get().paste_command != before.paste_command
+        {
+            self.clipboard = clipboard::detect();
+        }
+        self.update_layout();
+        Ok(())
+    }
+
+    /// Screen or views have changed, fit the focused view into its place.
+    fn update_layout(&mut self) {
+        let rect = self.layout.rects(Self::views_area(self.screen_size))[self.focus];
+        self.win_size = Self::view_size(rect, self.gutter_width(rect, &self.buffer.editor_rows));
+        self.buffer.cursor_controller.wrap = config::get().wrap;
+        self.buffer
+            .cursor_controller
+            .resize(self.win_size, &self.buffer.editor_rows);
+    }
+
+    /// Terminal has been resized to `(columns, rows)`, the whole screen is drawn again at next refresh.
+    pub fn resize(&mut self, size: (usize, usize)) {
+        self.screen_size = size;
+        self.update_layout();
+        queue!(self.editor_contents, terminal::Clear(ClearType::All)).unwrap();
+    }
+
+    /// Split the focused view into two views of the same buffer, the new one gets focus.
+    pub fn split_view(&mut self, direction: SplitDirection) {
+        let rect = self.layout.rects(Self::views_area(self.screen_size))[self.focus];
+        let enough_room = match direction {
+            SplitDirection::Horizontal => rect.height >= 4,
+            SplitDirection::Vertical => rect.width >= 3,
+        };
+        if !enough_room {
+            self.status_message
+                .set_message("Not enough room to split".into());
+            return;
+        }
+        self.buffer.history.seal();
+        self.save_view();
+        let view = *self.layout.views()[self.focus];
+        self.layout.split(self.focus, direction, view);
+        self.focus += 1;
+        self.update_layout();
+    }
+
+    /// Close the focused view, the buffer stays open.
+    pub fn close_view(&mut self) {
+        if !self.layout.close(self.focus) {
+            self.status_message.set_message("Only one view".into());
+            return;
+        }
+        self.focus_view(self.focus.min(self.layout.count() - 1));
+    }
+
+    /// Move focus to the next view, from top-left to bottom-right.
+    pub fn next_view(&mut self) {
+        if self.layout.count() == 1 {
+            self.status_message.set_message("Only one view".into());
+            return;
+        }
+        self.save_view();
+        self.focus_view((self.focus + 1) % self.layout.count());
+    }
+
+    /// Click to move the cursor, drag to select and turn the wheel to scroll, in the view under mouse.
+    /// Clicking in another view moves focus to it.
+    pub fn mouse(&mut self, event: MouseEvent) {
+        let (x, y) = (event.column as usize, event.row as usize);
+        let rects = self.layout.rects(Self::views_area(self.screen_size));
+        let under_mouse = rects.iter().position(|rect| rect.contains(x, y));
+        match (event.kind, under_mouse) {
+            (kind @ (MouseEventKind::ScrollUp | MouseEventKind::ScrollDown), Some(index)) => {
+                let down = matches!(kind, MouseEventKind::ScrollDown);
+                if index == self.focus {
+                    self.buffer.cursor_controller.scroll_lines(
+                        &self.buffer.editor_rows,
+                        down,
+                        config::get().scroll_lines,
+                    );
+                } else {
+                    let view = *self.layout.views()[index];
+                    let mut cursor_controller = view.cursor_controller;
+                    cursor_controller.scroll_lines(
+                        &self.buffer_at(view.buffer).editor_rows,
+                        down,
+                        config::get().scroll_lines,
+                    );
+                    self.layout.views_mut()[index].cursor_controller = cursor_controller;
+                }
+            }
+            (MouseEventKind::Down(MouseButton::Left), Some(index)) => {
+                if index != self.focus {
+                    self.save_view();
+                    self.focus_view(index);
+                }
+                let rect = rects[index];
+                // 点击状态栏只切换视图
+                if y == rect.y + rect.height - 1 {
+                    return;
+                }
+                self.buffer.history.seal();
+                let (cursor_x, cursor_y) = self.text_position(rect, x, y);
+                let cursor_controller = &mut self.buffer.cursor_controller;
+                cursor_controller.cursor_x = cursor_x;
+                cursor_controller.cursor_y = cursor_y;
+                // 拖动时从这里开始选择
+                cursor_controller.selection_anchor = Some((cursor_x, cursor_y));
+            }
+            (MouseEventKind::Drag(MouseButton::Left), _) => {
+                // 拖出视图时, 选择到视图的边缘
+                let rect = rects[self.focus];
+                let x = x.clamp(rect.x, rect.x + rect.width.saturating_sub(1));
+                let y = y.clamp(rect.y, rect.y + rect.height.saturating_sub(2));
+                let (cursor_x, cursor_y) = self.text_position(rect, x, y);
+                let cursor_controller = &mut self.buffer.cursor_controller;
+                cursor_controller
+                    .selection_anchor
+                    .get_or_insert((cursor_controller.cursor_x, cursor_controller.cursor_y));
+                cursor_controller.cursor_x = cursor_x;
+                cursor_controller.cursor_y = cursor_y;
+            }
+            _ => {}
+        }
+    }
+
+    /// Position in text of the focused view which is displayed at `(x, y)` of screen, `rect` is where the view is.
+    fn text_position(&self, rect: Rect, x: usize, y: usize) -> (usize, usize) {
+        let gutter = self.gutter_width(rect, &self.buffer.editor_rows);
+        self.buffer.cursor_controller.position_at(
+            &self.buffer.editor_rows,
+            ((x - rect.x).saturating_sub(gutter), y - rect.y),
+        )
+    }
+
+    /// Remember the cursor of the focused view, before focus leaves it.
+    fn save_view(&mut self) {
+        let cursor_controller = self.buffer.cursor_controller;
+        self.layout.views_mut()[self.focus].cursor_controller = cursor_controller;
+    }
+
+    /// Edit in view `index`, its buffer becomes the current buffer.
+    fn focus_view(&mut self, index: usize) {
+        let view = *self.layout.views()[index];
+        self.buffer.history.seal();
+        self.swap_buffer(view.buffer);
+        self.focus = index;
+        self.buffer.cursor_controller = view.cursor_controller;
+        // The buffer may have been edited in other views.
+        self.buffer
+            .cursor_controller
+            .clamp(&self.buffer.editor_rows);
+        self.update_layout();
+    }
+
+    pub fn clear_screen() -> crossterm::Result<()> {
+        execute!(stdout(), terminal::Clear(ClearType::All))?;
+        execute!(stdout(), cursor::MoveTo(0, 0))
+    }
+
+    pub fn move_cursor(&mut self, direction: KeyCode) {
+        self.buffer.history.seal();
+        self.buffer.cursor_controller.selection_anchor = None;
+        self.buffer
+            .cursor_controller
+            .move_cursor(direction, &self.buffer.editor_rows); // modify
+    }
+
+    /// Move cursor and extend the selection to it.
+    pub fn select(&mut self, direction: KeyCode) {
+        self.buffer.history.seal();
+        let cursor_controller = &mut self.buffer.cursor_controller;
+        cursor_controller
+            .selection_anchor
+            .get_or_insert((cursor_controller.cursor_x, cursor_controller.cursor_y));
+        cursor_controller.move_cursor(direction, &self.buffer.editor_rows);
+    }
+
+    pub fn select_all(&mut self) {
+        self.buffer.history.seal();
+        self.buffer.cursor_controller.selection_anchor = Some((0, 0));
+        (
+            self.buffer.cursor_controller.cursor_x,
+            self.buffer.cursor_controller.cursor_y,
+        ) = self
+            .buffer
+            .editor_rows
+            .clamp_position((0, self.buffer.editor_rows.number_of_rows()));
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.buffer.cursor_controller.selection_anchor = None;
+    }
+
+    /// Selected region, which never goes beyond the last row.
+    fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        Self::selection_in(&self.buffer.cursor_controller, &self.buffer.editor_rows)
+    }
+
+    /// Selected region of a view.
+    fn selection_in(
+        cursor_controller: &CursorController,
+        editor_rows: &EditorRows,
+    ) -> Option<((usize, usize), (usize, usize))> {
+        cursor_controller
+            .selection()
+            .map(|(start, end)| {
+                (
+                    editor_rows.clamp_position(start),
+                    editor_rows.clamp_position(end),
+                )
+            })
+            .filter(|(start, end)| start != end)
+    }
+
+    /// Clear the selection.
+    /// Returns the edits which delete the selected text, and the position where the next edits should be made.
+    fn take_selection(&mut self) -> (Vec<Edit>, (usize, usize)) {
+        let selection = self.selection();
+        self.buffer.cursor_controller.selection_anchor = None;
+        match selection {
+            Some((start, end)) => (
+                vec![Edit::Delete {
+                    at: start,
+                    text: self.buffer.editor_rows.text_between(start, end),
+                }],
+                start,
+            ),
+            None => (
+                Vec::new(),
+                (
+                    self.buffer.cursor_controller.cursor_x,
+                    self.buffer.cursor_controller.cursor_y,
+                ),
             ),
-            dirty: 0,
-            syntax_highlight,
-            previous_highlight: None,
         }
     }
 
-    pub fn select_syntax(extension: &str) -> Option<Box<dyn SyntaxHighlight>> {
-        let list: Vec<Box<dyn SyntaxHighlight>> = vec![Box::new(RustHighlight::new())];
-        // list.push(other highlight);
-        list.into_iter()
-            .find(|it| it.extensions().contains(&extension))
+    /// Copy the selected text into clipboard.
+    pub fn copy(&mut self) -> bool {
+        match self.selection() {
+            Some((start, end)) => {
+                let text = self.buffer.editor_rows.text_between(start, end);
+                match self.clipboard.copy(&text, &mut self.editor_contents) {
+                    Ok(()) => self
+                        .status_message
+                        .set_message(format!("{} bytes copied", text.len())),
+                    Err(err) => self
+                        .status_message
+                        .set_message(format!("Copy to clipboard failed: {}", err)),
+                }
+                true
+            }
+            None => {
+                self.status_message.set_message("Nothing selected".into());
+                false
+            }
+        }
     }
 
-    pub fn clear_screen() -> crossterm::Result<()> {
-        execute!(stdout(), terminal::Clear(ClearType::All))?;
-        execute!(stdout(), cursor::MoveTo(0, 0))
+    /// Move the selected text into clipboard.
+    pub fn cut(&mut self) {
+        if self.copy() {
+            let (edits, at) = self.take_selection();
+            self.commit(edits, at, false);
+        }
     }
 
-    pub fn move_cursor(&mut self, direction: KeyCode) {
-        self.cursor_controller
-            .move_cursor(direction, &self.editor_rows); // modify
+    /// Insert the text in clipboard at the cursor, it replaces the selected text.
+    pub fn paste(&mut self) {
+        let text = match self.clipboard.paste() {
+            Ok(text) if !text.is_empty() => text,
+            Ok(_) => return,
+            Err(err) => {
+                self.status_message
+                    .set_message(format!("Paste from clipboard failed: {}", err));
+                return;
+            }
+        };
+        self.insert_str(&text);
+    }
+
+    /// Insert a block of text as one edit, e.g. text pasted by terminal (bracketed paste).
+    /// Tabs and lone '\r' are kept as they are, "\r\n" becomes a line break of the buffer,
+    /// which is written as the line break of the file when saving.
+    pub fn insert_str(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n");
+        if text.is_empty() {
+            return;
+        }
+        let (mut edits, (x, y)) = self.take_selection();
+        if y == self.buffer.editor_rows.number_of_rows() {
+            edits.push(Edit::InsertRow { at: y });
+        }
+        let cursor_after = text_end((x, y), &text);
+        edits.push(Edit::Insert { at: (x, y), text });
+        self.commit(edits, cursor_after, false);
     }
 
     /* add this function */
-    fn draw_rows(&mut self) {
-        let screen_rows = self.win_size.1;
-        let screen_columns = self.win_size.0;
-        for i in 0..screen_rows {
-            let file_row = i + self.cursor_controller.row_offset;
-            if file_row >= self.editor_rows.number_of_rows() {
-                if self.editor_rows.number_of_rows() == 0 && i == screen_rows / 3 {
+    /// Draw rows of view `index` into `rect`, every line is padded to the width of `rect`.
+    fn draw_rows(&self, out: &mut EditorContents, index: usize, rect: Rect) {
+        let view = self.layout.views()[index];
+        let (buffer, cursor_controller) = if index == self.focus {
+            (&self.buffer, &self.buffer.cursor_controller)
+        } else {
+            (self.buffer_at(view.buffer), &view.cursor_controller)
+        };
+        let editor_rows = &buffer.editor_rows;
+        let gutter = self.gutter_width(rect, editor_rows);
+        let screen_rows = rect.height.saturating_sub(1);
+        let screen_columns = rect.width - gutter;
+        let selection = Self::selection_in(cursor_controller, editor_rows);
+        // 折行时一个文件行显示在多个屏幕行上
+        let screen_lines = cursor_controller.screen_lines(editor_rows);
+        for (i, (file_row, line, start, line_width)) in
+            screen_lines.into_iter().take(screen_rows).enumerate()
+        {
+            queue!(out, cursor::MoveTo(rect.x as u16, (rect.y + i) as u16)).unwrap();
+            if gutter > 0 {
+                self.draw_line_number(out, file_row, line, gutter, cursor_controller, editor_rows);
+            }
+            let width = if file_row >= editor_rows.number_of_rows() {
+                if editor_rows.number_of_rows() == 0 && i == screen_rows / 3 {
                     let mut welcome = format!("Pound Editor --- Version {}", VERSION);
                     if welcome.len() > screen_columns {
                         welcome.truncate(screen_columns)
                     }
                     let mut padding = (screen_columns - welcome.len()) / 2;
+                    let width = padding + welcome.len();
                     if padding != 0 {
-                        self.editor_contents.push('~');
+                        out.push('~');
                         padding -= 1
                     }
-                    (0..padding).for_each(|_| self.editor_contents.push(' '));
-                    self.editor_contents.push_str(&welcome);
+                    (0..padding).for_each(|_| out.push(' '));
+                    out.push_str(&welcome);
+                    width
                 } else {
-                    self.editor_contents.push('~');
+                    out.push('~');
+                    1
                 }
             } else {
-                let row = self.editor_rows.get_editor_row(file_row);
-                let render = &row.render;
-                let column_offset = self.cursor_controller.column_offset;
-                let len = cmp::min(
-                    row.render_len().saturating_sub(column_offset),
-                    screen_columns,
-                );
-                let start = if len == 0 { 0 } else { column_offset };
+                let row = editor_rows.get_editor_row(file_row);
+                let (padding, bytes, chars) = row.visible_render(start, line_width);
+                (0..padding).for_each(|_| out.push(' '));
+                let render = &row.render[bytes];
+                let mut highlight = match editor_rows.syntax_highlight {
+                    Some(_) => row.highlight[chars.clone()].to_vec(),
+                    None => vec![HighlightType::Normal; chars.len()],
+                };
+                let mut mark = |marked: Range<usize>, highlight_type: HighlightType| {
+                    chars
+                        .clone()
+                        .zip(highlight.iter_mut())
+                        .filter(|(i, _)| marked.contains(i))
+                        .for_each(|(_, it)| *it = highlight_type);
+                };
+                // 搜索结果属于当前缓冲区, 显示在它的所有视图中
+                if view.buffer == self.current {
+                    for matched in self.search.matches_in_row(&row.row_content) {
+                        mark(
+                            row.render_char_index(matched.start)
+                                ..row.render_char_index(matched.end),
+                            HighlightType::SearchMatch,
+                        );
+                    }
+                }
+                if let Some(selected) = selection.and_then(|it| row.selected_chars(file_row, it)) {
+                    mark(selected, HighlightType::Selection);
+                }
                 // self.editor_contents.push_str(&row[start..start + len]);
                 // let _ = &row[start..start + len]
                 //     .chars()
@@ -375,284 +1508,906 @@ impl Output {
                 - Method of 'as_ref" is used to avoid borrow checker error.
                 - Combine methods of 'map' and 'unwrap_or_else' to realize 'if else' functionality.
                 */
-                self.syntax_highlight
+                editor_rows
+                    .syntax_highlight
                     .as_ref()
-                    .map(|syntax_highlight| {
-                        syntax_highlight.color_row(
-                            &render[start..start + len],
-                            &row.highlight[start..start + len],
-                            &mut self.editor_contents,
+                    .map(|syntax_highlight| syntax_highlight.color_row(render, &highlight, out))
+                    .unwrap_or_else(|| {
+                        write_highlighted(
+                            render,
+                            &highlight,
+                            |highlight_type| match highlight_type {
+                                HighlightType::SearchMatch => Color::Blue,
+                                _ => Color::Reset,
+                            },
+                            out,
                         )
-                    })
-                    .unwrap_or_else(|| self.editor_contents.push_str(&render[start..start + len]));
-            }
-            queue!(
-                self.editor_contents,
-                terminal::Clear(ClearType::UntilNewLine)
-            )
-            .unwrap();
-            // if i < screen_rows - 1 {
-            self.editor_contents.push_str("\r\n");
-            // }
+                    });
+                padding + render.graphemes(true).map(|it| it.width()).sum::<usize>()
+            };
+            // 不能清除到行尾, 右边可能还有其他视图
+            (width..screen_columns).for_each(|_| out.push(' '));
+        }
+    }
+
+    /// Draw the line number of `file_row` right aligned in `gutter` columns, followed by a space.
+    /// Relative line numbers count from the row of cursor, which shows its absolute number.
+    /// Only the first line of a wrapped row has a number.
+    fn draw_line_number(
+        &self,
+        out: &mut EditorContents,
+        file_row: usize,
+        line: usize,
+        gutter: usize,
+        cursor_controller: &CursorController,
+        editor_rows: &EditorRows,
+    ) {
+        if file_row >= editor_rows.number_of_rows() || line > 0 {
+            (0..gutter).for_each(|_| out.push(' '));
+            return;
         }
+        let cursor_y = cursor_controller.cursor_y;
+        let number = match config::get().line_numbers {
+            LineNumbers::Relative if file_row != cursor_y => file_row.abs_diff(cursor_y),
+            _ => file_row + 1,
+        };
+        let color = if file_row == cursor_y {
+            Color::Reset
+        } else {
+            Color::DarkGrey
+        };
+        queue!(out, SetForegroundColor(color)).unwrap();
+        out.push_str(&format!("{:>1$} ", number, gutter - 1));
+        queue!(out, ResetColor).unwrap();
     }
 
-    fn draw_status_bar(&mut self) {
-        self.editor_contents
-            .push_str(&style::Attribute::Reverse.to_string());
+    /// Draw status bar of view `index` on the last line of `rect`.
+    fn draw_status_bar(&self, out: &mut EditorContents, index: usize, rect: Rect) {
+        let view = self.layout.views()[index];
+        let (buffer, cursor_controller) = if index == self.focus {
+            (&self.buffer, &self.buffer.cursor_controller)
+        } else {
+            (self.buffer_at(view.buffer), &view.cursor_controller)
+        };
+        queue!(
+            out,
+            cursor::MoveTo(rect.x as u16, (rect.y + rect.height - 1) as u16)
+        )
+        .unwrap();
+        out.push_str(&style::Attribute::Reverse.to_string());
+        // 有多个视图时, 用粗体标出正在编辑的视图
+        if index == self.focus && self.layout.count() > 1 {
+            out.push_str(&style::Attribute::Bold.to_string());
+        }
         let info = format!(
-            "{} {} -- {} lines",
-            self.editor_rows
-                .filename
-                .as_ref()
-                .and_then(|path| path.file_name())
-                .and_then(|name| name.to_str())
-                .unwrap_or("[No Name]"),
-            if self.dirty > 0 { "(modified)" } else { "" },
-            self.editor_rows.number_of_rows()
+            "{}{} {} -- {} lines",
+            if self.buffer_count() > 1 {
+                format!("[{}/{}] ", view.buffer + 1, self.buffer_count())
+            } else {
+                String::new()
+            },
+            buffer.name(),
+            if buffer.editor_rows.read_only {
+                "(read-only)"
+            } else if buffer.dirty > 0 {
+                "(modified)"
+            } else {
+                ""
+            },
+            buffer.editor_rows.number_of_rows()
         );
-        let info_len = cmp::min(info.len(), self.win_size.0);
+        let info = truncate_to_width(&info, rect.width);
+        let info_len = info.width();
         /* add the following*/
         let line_info = format!(
-            "{} | {}/{}",
-            self.syntax_highlight
+            "{} | {}{} | {} | {}/{}",
+            buffer
+                .editor_rows
+                .syntax_highlight
                 .as_ref()
                 .map(|highlight| highlight.file_type())
                 .unwrap_or("No ft"),
-            self.cursor_controller.cursor_y + 1,
-            self.editor_rows.number_of_rows()
+            buffer.editor_rows.file_format.line_ending.name(),
+            if buffer.editor_rows.file_format.bom {
+                " BOM"
+            } else {
+                ""
+            },
+            buffer.editor_rows.indent().name(),
+            cursor_controller.cursor_y + 1,
+            buffer.editor_rows.number_of_rows()
         );
-        self.editor_contents.push_str(&info[..info_len]);
-        for i in info_len..self.win_size.0 {
-            if self.win_size.0 - i == line_info.len() {
-                self.editor_contents.push_str(&line_info);
+        out.push_str(info);
+        for i in info_len..rect.width {
+            if rect.width - i == line_info.width() {
+                out.push_str(&line_info);
                 break;
             } else {
-                self.editor_contents.push(' ')
+                out.push(' ')
             }
         }
         /* end */
-        self.editor_contents
-            .push_str(&style::Attribute::Reset.to_string());
-        self.editor_contents.push_str("\r\n");
+        out.push_str(&style::Attribute::Reset.to_string());
+    }
+
+    /// Draw the columns between views which are side by side.
+    fn draw_separators(&self, out: &mut EditorContents) {
+        for separator in self.layout.separators(Self::views_area(self.screen_size)) {
+            for y in separator.y..separator.y + separator.height {
+                queue!(out, cursor::MoveTo(separator.x as u16, y as u16)).unwrap();
+                out.push('│');
+            }
+        }
     }
 
-    fn draw_message_bar(&mut self) {
+    fn draw_message_bar(&mut self, out: &mut EditorContents) {
         queue!(
-            self.editor_contents,
+            out,
+            cursor::MoveTo(0, self.screen_size.1.saturating_sub(1) as u16),
             terminal::Clear(ClearType::UntilNewLine)
         )
         .unwrap();
         if let Some(message) = self.status_message.message() {
-            let len = cmp::min(message.len(), self.win_size.0);
-            self.editor_contents.push_str(&message[..len]);
+            out.push_str(truncate_to_width(message, self.screen_size.0));
         }
     }
 
     pub fn insert_char(&mut self, ch: char) {
-        if self.cursor_controller.cursor_y == self.editor_rows.number_of_rows() {
-            self.editor_rows
-                .insert_row(self.editor_rows.number_of_rows(), String::new());
-            self.dirty += 1;
+        self.type_str(&ch.to_string());
+    }
+
+    /// Insert typed text which has no line break, it replaces the selected text.
+    fn type_str(&mut self, text: &str) {
+        let (mut edits, (x, y)) = self.take_selection();
+        if y == self.buffer.editor_rows.number_of_rows() {
+            edits.push(Edit::InsertRow { at: y });
         }
-        self.editor_rows
-            .get_editor_row_mut(self.cursor_controller.cursor_y)
-            .insert_char(self.cursor_controller.cursor_x, ch);
+        edits.push(Edit::Insert {
+            at: (x, y),
+            text: text.into(),
+        });
+        self.commit(edits, (x + text.len(), y), true);
+    }
+
+    /// Rows which are touched by the selection, or the row of cursor.
+    /// A selection ending at the start of a row doesn't include it.
+    fn selected_rows(&self) -> Range<usize> {
+        let rows = match self.selection() {
+            Some((start, end)) if end.0 == 0 && end.1 > start.1 => start.1..end.1,
+            Some((start, end)) => start.1..end.1 + 1,
+            None => {
+                let y = self.buffer.cursor_controller.cursor_y;
+                y..y + 1
+            }
+        };
+        rows.start..rows.end.min(self.buffer.editor_rows.number_of_rows())
+    }
 
-        // Update syntax highlighting
-        if let Some(it) = self.syntax_highlight.as_ref() {
-            it.update_syntax(
-                self.cursor_controller.cursor_y,
-                &mut self.editor_rows.row_contents,
-            )
+    /// Tab: indent the selected rows by one level, or insert indentation at the cursor to the next level.
+    pub fn indent(&mut self) {
+        let indent = self.buffer.editor_rows.indent();
+        if self.selection().is_none() {
+            let (x, y) = (
+                self.buffer.cursor_controller.cursor_x,
+                self.buffer.cursor_controller.cursor_y,
+            );
+            let render_x = if y < self.buffer.editor_rows.number_of_rows() {
+                self.buffer.editor_rows.get_editor_row(y).get_render_x(x)
+            } else {
+                0
+            };
+            self.type_str(&indent.soft_tab(render_x));
+            return;
         }
+        let unit = indent.unit();
+        let edits = self
+            .selected_rows()
+            .filter(|&y| self.buffer.editor_rows.row_len(y) > 0)
+            .map(|y| Edit::Insert {
+                at: (0, y),
+                text: unit.clone(),
+            })
+            .collect();
+        self.shift_rows(edits);
+    }
 
-        self.cursor_controller.cursor_x += 1;
-        self.dirty += 1;
+    /// Shift-Tab: remove one level of indentation from the selected rows or the row of cursor.
+    pub fn dedent(&mut self) {
+        let indent = self.buffer.editor_rows.indent();
+        let tab_stop = self.buffer.editor_rows.tab_stop();
+        let edits = self
+            .selected_rows()
+            .filter_map(|y| {
+                let row = self.buffer.editor_rows.row_content(y);
+                let len = indent.dedent_len(&row, tab_stop);
+                (len > 0).then(|| Edit::Delete {
+                    at: (0, y),
+                    text: row[..len].into(),
+                })
+            })
+            .collect();
+        self.shift_rows(edits);
+    }
+
+    /// Apply edits which insert or delete whitespace at the start of rows,
+    /// the cursor and the selection move with the text after the whitespace.
+    fn shift_rows(&mut self, edits: Vec<Edit>) {
+        if edits.is_empty() {
+            return;
+        }
+        let shift = |(x, y): (usize, usize)| {
+            let moved = edits.iter().find_map(|edit| match edit {
+                Edit::Insert { at, text } if at.1 == y => Some(x + text.len()),
+                Edit::Delete { at, text } if at.1 == y => Some(x.saturating_sub(text.len())),
+                _ => None,
+            });
+            // 选中整行时, 行首的位置保持不变
+            match moved {
+                Some(moved) if x > 0 => (moved, y),
+                _ => (x, y),
+            }
+        };
+        let cursor_controller = &self.buffer.cursor_controller;
+        let anchor = cursor_controller.selection_anchor.map(shift);
+        let cursor_after = shift((cursor_controller.cursor_x, cursor_controller.cursor_y));
+        self.commit(edits, cursor_after, false);
+        self.buffer.cursor_controller.selection_anchor = anchor;
     }
 
+    /// Split the row at cursor, the new row is indented like the row before it.
+    /// After an open bracket it's indented by one more level, and the closing bracket right after the cursor
+    /// goes to a row of its own which is indented like the open one.
     pub fn insert_newline(&mut self) {
-        /* Insert blank line. */
-        if self.cursor_controller.cursor_x == 0 {
-            self.editor_rows
-                .insert_row(self.cursor_controller.cursor_y, String::new())
+        // 选中的文本被删除后, 光标后面是选区结束位置后面的文本
+        let end = self.selection().map(|(_, end)| end);
+        let (mut edits, (x, y)) = self.take_selection();
+        let editor_rows = &self.buffer.editor_rows;
+        if y == editor_rows.number_of_rows() {
+            edits.push(Edit::InsertRow { at: y });
+            self.commit(edits, (0, y + 1), false);
+            return;
         }
+        let row = editor_rows.row_content(y);
+        let before = &row[..x];
+        let leading = &before[..before.len() - before.trim_start().len()];
+        let (end_x, end_y) = end.unwrap_or((x, y));
+        let after_row = editor_rows.row_content(end_y);
+        let after = &after_row[end_x..];
+
         /* Split line */
-        else {
-            let current_row = self
-                .editor_rows
-                .get_editor_row_mut(self.cursor_controller.cursor_y);
-            let new_row_content = current_row.row_content[self.cursor_controller.cursor_x..].into();
-            current_row
-                .row_content
-                .truncate(self.cursor_controller.cursor_x);
-            EditorRows::render_row(current_row);
-            self.editor_rows
-                .insert_row(self.cursor_controller.cursor_y + 1, new_row_content);
-
-            // Update highlight for the new row.
-            if let Some(it) = self.syntax_highlight.as_ref() {
-                it.update_syntax(
-                    self.cursor_controller.cursor_y,
-                    &mut self.editor_rows.row_contents,
-                );
-                it.update_syntax(
-                    self.cursor_controller.cursor_y + 1,
-                    &mut self.editor_rows.row_contents,
-                )
-            }
+        let mut text = format!("\n{}", leading);
+        let rules = editor_rows.indent_rules();
+        if rules.indents_after(before) {
+            text.push_str(&editor_rows.indent().unit());
         }
-        self.cursor_controller.cursor_x = 0;
-        self.cursor_controller.cursor_y += 1;
-        self.dirty += 1;
+        let cursor_after = text_end((x, y), &text);
+        if rules.splits_pair(before, after) {
+            text.push('\n');
+            text.push_str(leading);
+        }
+        edits.push(Edit::Insert { at: (x, y), text });
+        self.commit(edits, cursor_after, false);
     }
 
-    /// 删除光标前一个字符
+    /// 删除光标前一个字符, 有选中的文本时删除选中的文本
     pub fn delete_char(&mut self) {
-        if self.cursor_controller.cursor_y == self.editor_rows.number_of_rows() {
+        if self.selection().is_some() {
+            let (edits, at) = self.take_selection();
+            self.commit(edits, at, false);
             return;
         }
-        let row = self
-            .editor_rows
-            .get_editor_row_mut(self.cursor_controller.cursor_y);
-        if self.cursor_controller.cursor_x > 0 {
-            row.delete_char(self.cursor_controller.cursor_x - 1);
-            self.cursor_controller.cursor_x -= 1;
-        } else {
-            let previous_row_content = self
+        let (x, y) = (
+            self.buffer.cursor_controller.cursor_x,
+            self.buffer.cursor_controller.cursor_y,
+        );
+        if y == self.buffer.editor_rows.number_of_rows() || (x == 0 && y == 0) {
+            return;
+        }
+        if x > 0 {
+            let row = self.buffer.editor_rows.get_editor_row(y);
+            // 在行首的空格中删除一整级缩进
+            let start = match self
+                .buffer
                 .editor_rows
-                .get_editor_row(self.cursor_controller.cursor_y - 1);
-            self.cursor_controller.cursor_x = previous_row_content.origin_len();
-            self.editor_rows
-                .join_adjacent_rows(self.cursor_controller.cursor_y);
-            self.cursor_controller.cursor_y -= 1;
-        }
-        if let Some(it) = self.syntax_highlight.as_ref() {
-            it.update_syntax(
-                self.cursor_controller.cursor_y,
-                &mut self.editor_rows.row_contents,
+                .indent()
+                .backspace_len(&row.row_content[..x])
+            {
+                Some(len) => x - len,
+                None => row.previous_boundary(x),
+            };
+            let text = row.row_content[start..x].into();
+            drop(row);
+            self.commit(
+                vec![Edit::Delete {
+                    at: (start, y),
+                    text,
+                }],
+                (start, y),
+                false,
+            );
+        } else {
+            let previous_row_len = self.buffer.editor_rows.row_len(y - 1);
+            self.commit(
+                vec![Edit::Delete {
+                    at: (previous_row_len, y - 1),
+                    text: "\n".into(),
+                }],
+                (previous_row_len, y - 1),
+                false,
             );
         }
-        self.dirty += 1;
     }
 
-    fn find_callback(output: &mut Output, keyword: &str, key_code: KeyCode) {
-        // Restore highlight.
-        if let Some((row_index, highlight)) = output.previous_highlight.take() {
-            output.editor_rows.get_editor_row_mut(row_index).highlight = highlight;
+    /// 删除光标后一个字符, 有选中的文本时删除选中的文本
+    pub fn delete_forward(&mut self) {
+        if self.selection().is_some() {
+            let (edits, at) = self.take_selection();
+            self.commit(edits, at, false);
+            return;
         }
-        // Search for keyword.
-        match key_code {
-            KeyCode::Esc | KeyCode::Enter => {}
-            _ => {
-                // 默认查找范围是所有行
-                let mut line_rng = Either::Left(0..output.editor_rows.number_of_rows());
+        let (x, y) = (
+            self.buffer.cursor_controller.cursor_x,
+            self.buffer.cursor_controller.cursor_y,
+        );
+        let number_of_rows = self.buffer.editor_rows.number_of_rows();
+        if y >= number_of_rows {
+            return;
+        }
+        let row = self.buffer.editor_rows.get_editor_row(y);
+        let text = if x < row.row_content.len() {
+            row.row_content[x..row.next_boundary(x)].into()
+        } else if y + 1 < number_of_rows {
+            "\n".into()
+        } else {
+            return;
+        };
+        drop(row);
+        self.commit(vec![Edit::Delete { at: (x, y), text }], (x, y), false);
+    }
 
-                let cursor_x = output.cursor_controller.cursor_x;
-                let cursor_y = output.cursor_controller.cursor_y;
+    /// Remove whitespace at the end of rows before saving, if `.editorconfig` asks for it.
+    /// It's one undo step.
+    pub fn trim_trailing_whitespace(&mut self) {
+        let editor_rows = &self.buffer.editor_rows;
+        if !editor_rows.trim_trailing_whitespace {
+            return;
+        }
+        let edits: Vec<Edit> = (0..editor_rows.number_of_rows())
+            .filter_map(|y| {
+                let row = editor_rows.row_content(y);
+                let len = row.trim_end().len();
+                (len < row.len()).then(|| Edit::Delete {
+                    at: (len, y),
+                    text: row[len..].into(),
+                })
+            })
+            .collect();
+        if edits.is_empty() {
+            return;
+        }
+        let (x, y) = (
+            self.buffer.cursor_controller.cursor_x,
+            self.buffer.cursor_controller.cursor_y,
+        );
+        let cursor_after = edits
+            .iter()
+            .find_map(|edit| match edit {
+                Edit::Delete { at, .. } if at.1 == y => Some((x.min(at.0), y)),
+                _ => None,
+            })
+            .unwrap_or((x, y));
+        self.buffer.cursor_controller.selection_anchor = None;
+        self.commit(edits, cursor_after, false);
+    }
 
-                /*
-                Logic: (Now solution, but not good enough)
-                    1. If press Left or Right, search in line of cursor_y.
-                    2. If press Up or Down, make range in terms of direction, then search the range.
-                Optimization:
-                    1. If press Up or Down, find out the line which will be searched, or from first line of file.
-                    2. If press Left or Right, make x position in terms of direction, or from head of line.
-                    3. Search begin from the x in line.
-                */
-                match key_code {
-                    // 按下左右键, 在行内查找
-                    x_dir @ (KeyCode::Left | KeyCode::Right) => {
-                        let row = output.editor_rows.get_editor_row_mut(cursor_y);
-                        // 确定查找范围: 向左和向右
-                        if let KeyCode::Left = x_dir {
-                            if let Some(index) = row.render[..cursor_x].rfind(&keyword) {
-                                output.previous_highlight = Some((cursor_y, row.highlight.clone())); // backup
-                                (index..index + keyword.len()).for_each(|i| {
-                                    row.highlight[i] = HighlightType::SearchMatch;
-                                });
-                                output.cursor_controller.cursor_x = row.get_row_content_x(index);
-                            }
-                        } else {
-                            let start = (cursor_x).min(row.render.len());
-                            if let Some(index) = row.render[start..].find(&keyword) {
-                                output.previous_highlight = Some((cursor_y, row.highlight.clone())); // backup
-                                (start + index..start + index + keyword.len()).for_each(|i| {
-                                    row.highlight[i] = HighlightType::SearchMatch;
-                                });
-                                output.cursor_controller.cursor_x =
-                                    row.get_row_content_x(index + start);
-                            }
-                        };
+    /// Apply edits as one undo step, then move cursor to `cursor_after`.
+    /// `typing` edits are merged into the previous undo step if they continue it.
+    fn commit(&mut self, edits: Vec<Edit>, cursor_after: (usize, usize), typing: bool) {
+        let cursor_before = (
+            self.buffer.cursor_controller.cursor_x,
+            self.buffer.cursor_controller.cursor_y,
+        );
+        edits.iter().for_each(|edit| self.apply_edit(edit));
+        self.record(
+            Change {
+                edits,
+                cursor_before,
+                cursor_after,
+            },
+            typing,
+        );
+    }
 
-                        // 行内查找结束后, 返回
-                        return;
-                    }
-                    // 按下上下键, 在行间查找
-                    y_dir @ (KeyCode::Up | KeyCode::Down) => {
-                        // search line by line: (start_line, end_line)
+    /// Record a change whose edits have already been applied, then move cursor to its `cursor_after`.
+    fn record(&mut self, change: Change, typing: bool) {
+        (
+            self.buffer.cursor_controller.cursor_x,
+            self.buffer.cursor_controller.cursor_y,
+        ) = change.cursor_after;
+        self.buffer.history.record(change, typing);
+        self.buffer.dirty += 1;
+    }
 
-                        if KeyCode::Up == y_dir {
-                            line_rng = Either::Right((0..cursor_y).rev());
-                        } else {
-                            let start_line =
-                                (cursor_y + 1).min(output.editor_rows.number_of_rows() - 1);
-                            line_rng =
-                                Either::Left(start_line..output.editor_rows.number_of_rows());
-                        }
-                    }
-                    _ => {}
-                };
+    fn apply_edit(&mut self, edit: &Edit) {
+        // Syntax highlighting is updated when the rows are rendered again.
+        self.buffer.editor_rows.apply_edit(edit);
+        // 同一缓冲区的其他视图中, 光标和选区随着文本移动
+        let (current, focus) = (self.current, self.focus);
+        for (index, view) in self.layout.views_mut().into_iter().enumerate() {
+            if index == focus || view.buffer != current {
+                continue;
+            }
+            let cursor_controller = &mut view.cursor_controller;
+            (cursor_controller.cursor_x, cursor_controller.cursor_y) =
+                edit.shift((cursor_controller.cursor_x, cursor_controller.cursor_y));
+            cursor_controller.selection_anchor =
+                cursor_controller.selection_anchor.map(|it| edit.shift(it));
+        }
+    }
+
+    pub fn undo(&mut self) {
+        match self.buffer.history.undo() {
+            Some(change) => self.replay(change),
+            None => self
+                .status_message
+                .set_message("Already at oldest change".into()),
+        }
+    }
 
-                //
-                for i in line_rng {
-                    let row = output.editor_rows.get_editor_row_mut(i);
+    pub fn redo(&mut self) {
+        match self.buffer.history.redo() {
+            Some(change) => self.replay(change),
+            None => self
+                .status_message
+                .set_message("Already at newest change".into()),
+        }
+    }
 
-                    if let Some(index) = row.render.find(&keyword) {
-                        output.previous_highlight = Some((i, row.highlight.clone())); // backup
-                        (index..index + keyword.len()).for_each(|at| {
-                            row.highlight[at] = HighlightType::SearchMatch;
-                        });
+    /// Apply a change taken from history without recording it again.
+    fn replay(&mut self, change: Change) {
+        change.edits.iter().for_each(|edit| self.apply_edit(edit));
+        // 选区可能已经不在文本中了
+        self.buffer.cursor_controller.selection_anchor = None;
+        (
+            self.buffer.cursor_controller.cursor_x,
+            self.buffer.cursor_controller.cursor_y,
+        ) = change.cursor_after;
+        self.buffer.dirty = if self.buffer.history.is_saved() {
+            0
+        } else {
+            self.buffer.dirty + 1
+        };
+    }
 
-                        output.cursor_controller.cursor_y = i;
-                        output.cursor_controller.cursor_x = row.get_row_content_x(index);
-                        output.cursor_controller.row_offset = output.editor_rows.number_of_rows();
-                        break;
-                    }
+    /// Called by prompt after each key: Alt-R / Alt-C / Alt-W toggle regex, ignore case and whole word,
+    /// arrows go to the previous / next match, other keys change the keyword.
+    /// Returns the status of the search which is shown after the prompt.
+    fn find_callback(output: &mut Output, keyword: &str, key_event: KeyEvent) -> String {
+        let search = &mut output.search;
+        let found = match key_event {
+            KeyEvent {
+                code: KeyCode::Char(c @ ('r' | 'c' | 'w')),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => {
+                let options = &mut search.options;
+                match c {
+                    'r' => options.regex = !options.regex,
+                    'c' => options.ignore_case = !options.ignore_case,
+                    _ => options.whole_word = !options.whole_word,
                 }
+                search.update(keyword);
+                search.jump(search.origin, false, &output.buffer.editor_rows)
+            }
+            KeyEvent {
+                code: KeyCode::Left | KeyCode::Up,
+                ..
+            } => search.step(true, &output.buffer.editor_rows),
+            KeyEvent {
+                code: KeyCode::Right | KeyCode::Down,
+                ..
+            } => search.step(false, &output.buffer.editor_rows),
+            _ => {
+                search.update(keyword);
+                search.jump(search.origin, false, &output.buffer.editor_rows)
             }
+        };
+        if let Some((x, y)) = found {
+            output.buffer.cursor_controller.cursor_x = x;
+            output.buffer.cursor_controller.cursor_y = y;
+            // 滚动到匹配所在的行, 让它显示在屏幕顶部
+            output.buffer.cursor_controller.row_offset = output.buffer.editor_rows.number_of_rows();
         }
+        search.status()
     }
 
+    /// Search in prompt, the cursor stays at the match after Enter, or goes back after ESC.
     pub fn find(&mut self) -> io::Result<()> {
-        let cursor_controller = self.cursor_controller;
-        prompt!(
+        let cursor_controller = self.buffer.cursor_controller;
+        self.search.origin = (cursor_controller.cursor_x, cursor_controller.cursor_y);
+        let keyword = prompt!(
             self,
-            "Search: {} (Use ESC / Arrows / Enter)",
+            "Search: {} (ESC / Arrows / Enter | Alt-R regex, Alt-C case, Alt-W word)",
             callback = Output::find_callback
         );
-        self.cursor_controller = cursor_controller;
+        self.search.clear();
+        if keyword.filter(|it| !it.is_empty()).is_none() {
+            self.buffer.cursor_controller = cursor_controller;
+        }
         Ok(())
     }
 
+    /// Write unsaved changes to swap file from time to time, and keep them to be written if the editor panics.
+    pub fn update_swap(&mut self) {
+        crate::crash::keep_buffers(
+            self.all_buffers()
+                .filter(|buffer| buffer.dirty > 0)
+                .map(|buffer| {
+                    (
+                        buffer.editor_rows.filename.clone(),
+                        buffer.editor_rows.text.clone(),
+                    )
+                })
+                .collect(),
+        );
+        for buffer in std::iter::once(&mut self.buffer).chain(&mut self.buffers) {
+            if let Err(err) = buffer.swap.update(
+                buffer.editor_rows.filename.as_deref(),
+                &buffer.editor_rows.text,
+                buffer.dirty,
+            ) {
+                self.status_message
+                    .set_message(format!("Can't write swap file: {}", err));
+            }
+        }
+    }
+
+    /// The editor quits normally, swap files aren't needed anymore.
+    pub fn remove_swap(&mut self) {
+        std::iter::once(&mut self.buffer)
+            .chain(&mut self.buffers)
+            .for_each(|buffer| buffer.swap.remove());
+    }
+
+    /// Ask what to do with the swap file left by another editor, if it's found when the file is opened.
+    pub fn recover_swap(&mut self) -> io::Result<()> {
+        let Some(stale_swap) = self.buffer.editor_rows.stale_swap.take() else {
+            return Ok(());
+        };
+        let mut message = format!(
+            "Found swap file {}{}. (r)ecover / (d)iff / (x) delete it / ESC ignore",
+            stale_swap.path.display(),
+            match stale_swap.pid {
+                Some(pid) if stale_swap.running => format!(", process {} is still editing it", pid),
+                _ => String::new(),
+            }
+        );
+        loop {
+            self.status_message.set_message(message.clone());
+            self.refresh_screen()?;
+            let Some(event) = Reader.read_event()? else {
+                continue;
+            };
+            match event {
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('r'),
+                    ..
+                }) => {
+                    match stale_swap.read() {
+                        Ok(text) => self.recover(text),
+                        Err(err) => self
+                            .status_message
+                            .set_message(format!("Can't read swap file: {}", err)),
+                    }
+                    // 恢复的内容会被写入新的交换文件, 如果另一个编辑器还在运行, 就不要覆盖它的交换文件
+                    self.buffer.swap.alternate = stale_swap.running;
+                    return Ok(());
+                }
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('d'),
+                    ..
+                }) => match stale_swap.read() {
+                    Ok(text) => {
+                        message = format!(
+                            "{}. (r)ecover / (x) delete it / ESC ignore",
+                            swap::diff_summary(&self.buffer.editor_rows.text, &text)
+                        )
+                    }
+                    Err(err) => message = format!("Can't read swap file: {}", err),
+                },
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('x'),
+                    ..
+                }) => {
+                    if let Err(err) = stale_swap.discard() {
+                        self.status_message
+                            .set_message(format!("Can't delete swap file: {}", err));
+                    } else {
+                        self.status_message.set_message(String::new());
+                    }
+                    return Ok(());
+                }
+                Event::Key(KeyEvent {
+                    code: KeyCode::Esc, ..
+                }) => {
+                    self.status_message.set_message(String::new());
+                    self.buffer.swap.alternate = true;
+                    return Ok(());
+                }
+                Event::Resize(columns, rows) => self.resize((columns as usize, rows as usize)),
+                _ => {}
+            }
+        }
+    }
+
+    /// Replace the buffer by the text recovered from swap file, as one undo step.
+    fn recover(&mut self, text: Rope) {
+        let rows = self.buffer.editor_rows.number_of_rows();
+        let mut edits = vec![if rows == 0 {
+            Edit::InsertRow { at: 0 }
+        } else {
+            Edit::Delete {
+                at: (0, 0),
+                text: self
+                    .buffer
+                    .editor_rows
+                    .text_between((0, 0), self.buffer.editor_rows.clamp_position((0, rows))),
+            }
+        }];
+        // Every row is terminated by '\n', the last one is kept by the edits above.
+        let text = text.slice(..text.len_chars().saturating_sub(1)).to_string();
+        let cursor_after = text_end((0, 0), &text);
+        if !text.is_empty() {
+            edits.push(Edit::Insert { at: (0, 0), text });
+        }
+        self.commit(edits, cursor_after, false);
+        self.buffer.cursor_controller.selection_anchor = None;
+        self.status_message
+            .set_message("Recovered from swap file, save it to keep the changes".into());
+    }
+
+    /// Convert the line breaks of the file between LF and CRLF, it takes effect when the file is saved.
+    pub fn toggle_line_ending(&mut self) {
+        let from = self.buffer.editor_rows.file_format.line_ending;
+        let cursor = (
+            self.buffer.cursor_controller.cursor_x,
+            self.buffer.cursor_controller.cursor_y,
+        );
+        self.commit(
+            vec![Edit::LineEnding {
+                from,
+                to: from.toggled(),
+            }],
+            cursor,
+            false,
+        );
+        self.status_message.set_message(format!(
+            "Line endings will be saved as {}",
+            from.toggled().name()
+        ));
+    }
+
+    /// Find and replace in the selection, or in the whole buffer when nothing is selected.
+    /// Each match is confirmed by y / n / a (all the rest) / q, all replacements are one undo step.
+    /// With regex, `$1` or `${name}` in the replacement are replaced by the groups of the match.
+    pub fn replace(&mut self) -> io::Result<()> {
+        let cursor_controller = self.buffer.cursor_controller;
+        let cursor_before = (cursor_controller.cursor_x, cursor_controller.cursor_y);
+        let scope = self.selection().unwrap_or((
+            (0, 0),
+            self.buffer
+                .editor_rows
+                .clamp_position((0, self.buffer.editor_rows.number_of_rows())),
+        ));
+        self.search.origin = scope.0;
+        let keyword = prompt!(
+            self,
+            "Replace: {} (ESC / Enter | Alt-R regex, Alt-C case, Alt-W word)",
+            callback = Output::find_callback
+        );
+        self.search.clear();
+        self.buffer.cursor_controller = cursor_controller;
+        let Some(keyword) = keyword.filter(|it| !it.is_empty()) else {
+            return Ok(());
+        };
+        let regex = match self.search.options.compile(&keyword) {
+            Ok(regex) => regex,
+            Err(_) => {
+                self.status_message
+                    .set_message(format!("Invalid regex: {}", keyword));
+                return Ok(());
+            }
+        };
+        let Some(replacement) = prompt!(self, "Replace with: {}") else {
+            self.status_message.set_message("Replace aborted".into());
+            return Ok(());
+        };
+
+        let ((mut x, mut y), mut end) = scope;
+        let mut edits = Vec::new();
+        let mut replace_all = false;
+        let mut cursor_after = cursor_before;
+        let mut error = None; // The replacements made before an error are still recorded.
+        while (y, x) < (end.1, end.0) {
+            let row_content = self.buffer.editor_rows.row_content(y).into_owned();
+            let Some(captures) = regex.captures_at(&row_content, x).filter(|it| {
+                let matched = it.get(0).unwrap();
+                y < end.1 || matched.end() <= end.0
+            }) else {
+                (x, y) = (0, y + 1);
+                continue;
+            };
+            let matched = captures.get(0).unwrap();
+            // Empty matches and matches which split a grapheme are skipped, like in find.
+            if matched.is_empty()
+                || !is_grapheme_boundary(&row_content, matched.start())
+                || !is_grapheme_boundary(&row_content, matched.end())
+            {
+                x = matched.start()
+                    + row_content[matched.start()..]
+                        .chars()
+                        .next()
+                        .map_or(1, char::len_utf8);
+                continue;
+            }
+
+            if !replace_all {
+                // 选中当前的匹配, 等待确认
+                self.buffer.cursor_controller.cursor_x = matched.start();
+                self.buffer.cursor_controller.cursor_y = y;
+                self.buffer.cursor_controller.selection_anchor = Some((matched.end(), y));
+                self.status_message
+                    .set_message("Replace this match? (y)es / (n)o / (a)ll / (q)uit".into());
+                let event = match self.refresh_screen().and_then(|_| Reader.read_event()) {
+                    Ok(Some(event)) => event,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        error = Some(err);
+                        break;
+                    }
+                };
+                match event {
+                    Event::Key(KeyEvent {
+                        code: KeyCode::Char('y' | ' '),
+                        ..
+                    }) => {}
+                    Event::Key(KeyEvent {
+                        code: KeyCode::Char('n'),
+                        ..
+                    }) => {
+                        x = matched.end();
+                        continue;
+                    }
+                    Event::Key(KeyEvent {
+                        code: KeyCode::Char('a'),
+                        ..
+                    }) => replace_all = true,
+                    Event::Key(KeyEvent {
+                        code: KeyCode::Char('q') | KeyCode::Esc | KeyCode::Enter,
+                        ..
+                    }) => break,
+                    Event::Resize(columns, rows) => {
+                        self.resize((columns as usize, rows as usize));
+                        continue;
+                    }
+                    _ => continue,
+                }
+            }
+
+            let text = if self.search.options.regex {
+                let mut text = String::new();
+                captures.expand(&replacement, &mut text);
+                text
+            } else {
+                replacement.clone()
+            };
+            let at = (matched.start(), y);
+            let delete = Edit::Delete {
+                at,
+                text: matched.as_str().into(),
+            };
+            self.apply_edit(&delete);
+            edits.push(delete);
+            if !text.is_empty() {
+                let insert = Edit::Insert {
+                    at,
+                    text: text.clone(),
+                };
+                self.apply_edit(&insert);
+                edits.push(insert);
+            }
+            if y == end.1 {
+                end.0 = end.0 - matched.len() + text.len();
+            }
+            x = matched.start() + text.len();
+            cursor_after = (x, y);
+        }
+
+        self.buffer.cursor_controller.selection_anchor = None;
+        let replaced = edits
+            .iter()
+            .filter(|it| matches!(it, Edit::Delete { .. }))
+            .count();
+        if replaced == 0 {
+            self.buffer.cursor_controller = cursor_controller;
+            self.status_message
+                .set_message(format!("No match replaced: {}", keyword));
+        } else {
+            self.record(
+                Change {
+                    edits,
+                    cursor_before,
+                    cursor_after,
+                },
+                false,
+            );
+            self.status_message
+                .set_message(format!("Replaced {} match(es)", replaced));
+        }
+        error.map_or(Ok(()), Err)
+    }
+
     /// refresh screen
     /// 1. clear screen
     /// 2. move cursor to top-left
     /// 3. draw rows
     /// 4. move cursor to top-left
     pub fn refresh_screen(&mut self) -> crossterm::Result<()> {
-        self.cursor_controller.scroll(&self.editor_rows); //modify
-        queue!(self.editor_contents, cursor::Hide, cursor::MoveTo(0, 0))?;
-        self.draw_rows();
-        self.draw_status_bar(); // add line
-        self.draw_message_bar();
-        let cursor_x = self.cursor_controller.render_x - self.cursor_controller.column_offset; // modify
-        let cursor_y = self.cursor_controller.cursor_y - self.cursor_controller.row_offset;
-        queue!(
-            self.editor_contents,
-            cursor::MoveTo(cursor_x as u16, cursor_y as u16),
-            cursor::Show
-        )?;
+        // 行数的位数变化时, 行号的宽度也会变化
+        self.update_layout(); //modify
+        let row_offset = self.buffer.cursor_controller.row_offset;
+        self.buffer
+            .editor_rows
+            .shrink_cache(row_offset..row_offset + self.win_size.1);
+        let rects = self.layout.rects(Self::views_area(self.screen_size));
+        // 其他视图的缓冲区可能已经被修改, 让它们的光标留在文本中
+        for (index, rect) in rects.iter().enumerate() {
+            if index == self.focus {
+                continue;
+            }
+            let mut cursor_controller = self.layout.views()[index].cursor_controller;
+            let editor_rows = &self
+                .buffer_at(self.layout.views()[index].buffer)
+                .editor_rows;
+            cursor_controller.clamp(editor_rows);
+            cursor_controller.wrap = config::get().wrap;
+            cursor_controller.resize(
+                Self::view_size(*rect, self.gutter_width(*rect, editor_rows)),
+                editor_rows,
+            );
+            self.layout.views_mut()[index].cursor_controller = cursor_controller;
+        }
+
+        let mut out = std::mem::take(&mut self.editor_contents);
+        queue!(out, cursor::Hide)?;
+        // 终端缩得太小时, 有的视图没有空间, 不画它们
+        for (index, rect) in rects
+            .iter()
+            .enumerate()
+            .filter(|(_, rect)| !rect.is_empty())
+        {
+            self.draw_rows(&mut out, index, *rect);
+            self.draw_status_bar(&mut out, index, *rect); // add line
+        }
+        self.draw_separators(&mut out);
+        self.draw_message_bar(&mut out);
+        let rect = rects[self.focus];
+        // 视图被鼠标滚轮滚走, 或者视图小得只剩状态栏时, 光标不在屏幕上, 不显示它
+        if let Some((x, y)) = self
+            .buffer
+            .cursor_controller
+            .screen_position(&self.buffer.editor_rows)
+            .filter(|_| rect.width > 0 && rect.height > 1)
+        {
+            let cursor_x = rect.x + self.gutter_width(rect, &self.buffer.editor_rows) + x; // modify
+            let cursor_y = rect.y + y;
+            queue!(
+                out,
+                cursor::MoveTo(cursor_x as u16, cursor_y as u16),
+                cursor::Show
+            )?;
+        }
+        self.editor_contents = out;
         self.editor_contents.flush()
     }
 }
@@ -665,13 +2420,6 @@ fn is_separator(c: char) -> bool {
         .contains(&c)
 }
 
-enum Direction {
-    Up,
-    Down,
-    Left,
-    Right,
-}
-
 use crate::syntax_struct;
 
 /// Describe what type each char should be given in specific syntax rules.
@@ -680,13 +2428,14 @@ pub enum HighlightType {
     Normal,
     Number,
     SearchMatch,
+    Selection,
     String,
     CharLiteral,
     Comment,
     Other(Color),
 }
 
-/// 这个宏的作用是将一些数据转换成一个结构体, 虽然传入宏的参数看起来像结构体, 但并不是结构体.
+// 这个宏的作用是将一些数据转换成一个结构体, 虽然传入宏的参数看起来像结构体, 但并不是结构体.
 syntax_struct! {
     struct RustHighlight {
         // 可能有多个扩展名
@@ -705,11 +2454,10 @@ syntax_struct! {
     }
 }
 
-use std::{thread, time};
 /// This is a role who is responsible for highlight.
-pub trait SyntaxHighlight {
+pub trait SyntaxHighlight: IndentRules {
     // Update the syntax highlighting for the chars in current line.
-    fn update_syntax(&self, at: usize, editor_rows: &mut Vec<Row>);
+    fn update_syntax(&self, row: &mut Row, in_block_comment: bool);
     // Convert type to color
     fn syntax_color(&self, highlight_type: &HighlightType) -> Color; // add method
 
@@ -717,11 +2465,43 @@ pub trait SyntaxHighlight {
 
     // Write to editor.output.buffer
     fn color_row(&self, render: &str, highlight: &[HighlightType], out: &mut EditorContents) {
-        let mut current_color = self.syntax_color(&HighlightType::Normal);
+        write_highlighted(
+            render,
+            highlight,
+            |highlight_type| self.syntax_color(highlight_type),
+            out,
+        )
+    }
 
-        render.chars().enumerate().for_each(|(i, c)| {
-            let color = self.syntax_color(&highlight[i]);
+    fn extensions(&self) -> &[&str];
+}
+
+/// Write chars of render in the color of their highlight type, selected chars are displayed in reverse.
+fn write_highlighted(
+    render: &str,
+    highlight: &[HighlightType],
+    color: impl Fn(&HighlightType) -> Color,
+    out: &mut EditorContents,
+) {
+    let mut current_color = color(&HighlightType::Normal);
+    let mut selected = false;
+
+    render
+        .chars()
+        .zip(highlight)
+        .for_each(|(c, highlight_type)| {
+            let is_selected = *highlight_type == HighlightType::Selection;
+            if selected != is_selected {
+                let attribute = if is_selected {
+                    Attribute::Reverse
+                } else {
+                    Attribute::NoReverse
+                };
+                let _ = queue!(out, SetAttribute(attribute));
+                selected = is_selected;
+            }
 
+            let color = color(highlight_type);
             if current_color != color {
                 let _ = queue!(out, SetForegroundColor(color));
             }
@@ -730,15 +2510,12 @@ pub trait SyntaxHighlight {
             current_color = color;
         });
 
-        let _ = queue!(out, ResetColor);
+    if selected {
+        let _ = queue!(out, SetAttribute(Attribute::NoReverse));
     }
-
-    fn extensions(&self) -> &[&str];
+    let _ = queue!(out, ResetColor);
 }
 
-use super::reader::Reader;
-use crossterm::event::{KeyEvent, KeyModifiers};
-
 #[macro_export]
 macro_rules! log_print {
     ($($arg : tt) *) => {
@@ -750,10 +2527,12 @@ lazy_static! {
     static ref LOG: Mutex<Log> = Mutex::new(Log::new());
 }
 
+#[allow(dead_code)]
 struct Log {
     file: Option<File>,
 }
 
+#[allow(dead_code)]
 impl Log {
     fn new() -> Self {
         let file = fs::OpenOptions::new()
@@ -770,8 +2549,6 @@ impl Log {
     }
 }
 
-use crate::log_print;
-
 #[macro_export]
 macro_rules! syntax_struct {
     (
@@ -795,12 +2572,15 @@ macro_rules! syntax_struct {
             }
         }
 
+        impl IndentRules for $Name {}
+
         impl SyntaxHighlight for $Name {
             fn syntax_color(&self, highlight_type: &HighlightType) -> Color {
                 match highlight_type {
                     HighlightType::Normal => Color::Reset,
                     HighlightType::Number => Color::Cyan,
                     HighlightType::SearchMatch => Color::Blue,
+                    HighlightType::Selection => Color::Reset,
                     HighlightType::String => Color::Magenta,
                     HighlightType::CharLiteral => Color::DarkMagenta,
                     HighlightType::Comment => Color::Green,
@@ -816,9 +2596,9 @@ macro_rules! syntax_struct {
                 self.file_type
             }
 
-            fn update_syntax(&self, at: usize, editor_rows: &mut Vec<Row>) {
-                let mut in_block_comment = at>0 && editor_rows[at-1].is_comment;
-                let current_row = &mut editor_rows[at];
+            fn update_syntax(&self, row: &mut Row, in_block_comment: bool) {
+                let mut in_block_comment = in_block_comment;
+                let current_row = row;
                 macro_rules! add {
                     ($h:expr) => {{
                         current_row.highlight.push($h);
@@ -826,8 +2606,8 @@ macro_rules! syntax_struct {
                     }};
                 }
 
-                current_row.highlight = Vec::with_capacity(current_row.render.len());
                 let chars = &current_row.render.chars().collect::<Vec<char>>(); // slice
+                current_row.highlight = Vec::with_capacity(chars.len());
 
                 // for c in chars {
                 //     if c.is_digit(10) {
@@ -978,8 +2758,170 @@ macro_rules! syntax_struct {
                     i += 1;
                 }
                 current_row.is_comment = in_block_comment;
-                assert_eq!(current_row.render.len(), current_row.highlight.len())
+                assert_eq!(chars.len(), current_row.highlight.len())
             }
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    /// Rows of `text`, which is terminated by '\n'.
+    fn rows_of(text: &str) -> EditorRows {
+        let mut editor_rows = EditorRows::empty(None);
+        editor_rows.text = Rope::from_str(text);
+        editor_rows
+    }
+
+    #[test]
+    fn selection_is_clamped_into_rows() {
+        // Shift-Home 选中 "hello world" 之后撤销, 选区的起点留在已经变空的行之后
+        let editor_rows = rows_of("a\n\n");
+        let mut cursor_controller = CursorController::new((80, 24));
+        cursor_controller.cursor_y = 1;
+        cursor_controller.selection_anchor = Some((11, 1));
+        assert_eq!(Output::selection_in(&cursor_controller, &editor_rows), None);
+        cursor_controller.cursor_y = 0;
+        assert_eq!(
+            Output::selection_in(&cursor_controller, &editor_rows),
+            Some(((0, 0), (0, 1)))
+        );
+        assert_eq!(editor_rows.clamp_position((5, 7)), (0, 1));
+        assert_eq!(rows_of("é\n").clamp_position((1, 0)), (0, 0));
+    }
+
+    /// Rows of `editor_rows`, checking that the cached rows agree with the text.
+    fn rows(editor_rows: &EditorRows) -> Vec<String> {
+        (0..editor_rows.number_of_rows())
+            .map(|i| {
+                let row_content = editor_rows.row_content(i).into_owned();
+                assert_eq!(editor_rows.get_editor_row(i).row_content, row_content);
+                row_content
+            })
+            .collect()
+    }
+
+    #[test]
+    fn apply_edit_changes_rows_and_cache() {
+        let mut editor_rows = rows_of("héllo\nworld\n");
+        rows(&editor_rows);
+        let insert = Edit::Insert {
+            at: (3, 0),
+            text: ", big\nnew ".into(),
+        };
+        editor_rows.apply_edit(&insert);
+        assert_eq!(rows(&editor_rows), ["hé, big", "new llo", "world"]);
+        editor_rows.apply_edit(&Edit::Delete {
+            at: (3, 0),
+            text: ", big\nnew ".into(),
+        });
+        assert_eq!(rows(&editor_rows), ["héllo", "world"]);
+
+        editor_rows.apply_edit(&Edit::InsertRow { at: 1 });
+        assert_eq!(rows(&editor_rows), ["héllo", "", "world"]);
+        editor_rows.apply_edit(&Edit::DeleteRow { at: 1 });
+        assert_eq!(rows(&editor_rows), ["héllo", "world"]);
+
+        editor_rows.apply_edit(&Edit::LineEnding {
+            from: LineEnding::Lf,
+            to: LineEnding::Crlf,
+        });
+        assert_eq!(editor_rows.file_format.line_ending, LineEnding::Crlf);
+    }
+
+    #[test]
+    fn delete_text_joins_rows() {
+        let mut editor_rows = rows_of("one\ntwo\nthree\nfour\n");
+        rows(&editor_rows);
+        editor_rows.delete_text(1, 0, "ne\ntwo\nth");
+        assert_eq!(rows(&editor_rows), ["oree", "four"]);
+        editor_rows.delete_text(4, 0, "\n");
+        assert_eq!(rows(&editor_rows), ["oreefour"]);
+        editor_rows.delete_text(0, 0, "oreefour");
+        assert_eq!(rows(&editor_rows), [""]);
+    }
+
+    /// Rows read from a file of `bytes`, and whether it has mixed line endings.
+    fn read(name: &str, bytes: &[u8]) -> (EditorRows, bool) {
+        let file = env::temp_dir().join(format!("pound-read-{}-{}", process::id(), name));
+        fs::write(&file, bytes).unwrap();
+        let result = EditorRows::read_text(&file, false);
+        fs::remove_file(&file).unwrap();
+        let (text, file_format, mixed) = result.unwrap();
+        let mut editor_rows = EditorRows::empty(None);
+        editor_rows.text = text;
+        editor_rows.file_format = file_format;
+        (editor_rows, mixed)
+    }
+
+    fn written(editor_rows: &EditorRows) -> Vec<u8> {
+        let mut written = Vec::new();
+        let len = editor_rows.write_contents(&mut written).unwrap();
+        assert_eq!(len, written.len());
+        written
+    }
+
+    /// Read `bytes` from a file and write them back, the result must be the same bytes.
+    fn round_trip(name: &str, bytes: &[u8]) -> (EditorRows, bool) {
+        let (editor_rows, mixed) = read(name, bytes);
+        assert!(
+            written(&editor_rows) == bytes,
+            "{} isn't written as it's read",
+            name
+        );
+        (editor_rows, mixed)
+    }
+
+    #[test]
+    fn crlf_split_by_chunk_is_kept() {
+        // "\r" 是第一块的最后一个字节, "\n" 在第二块
+        let mut bytes = vec![b'a'; READ_CHUNK - 1];
+        bytes.extend_from_slice(b"\r\nb\r\n");
+        let (editor_rows, mixed) = round_trip("crlf", &bytes);
+        assert_eq!(editor_rows.file_format.line_ending, LineEnding::Crlf);
+        assert!(!mixed);
+        assert_eq!(editor_rows.number_of_rows(), 2);
+        assert_eq!(editor_rows.row_len(0), READ_CHUNK - 1);
+        assert_eq!(editor_rows.row_content(1), "b");
+    }
+
+    #[test]
+    fn bom_and_char_split_by_chunk_are_kept() {
+        // 加上 BOM 之后, "€" 的三个字节跨过块的边界
+        let mut bytes = BOM.as_bytes().to_vec();
+        bytes.extend(vec![b'a'; READ_CHUNK - BOM.len() - 1]);
+        bytes.extend_from_slice("€\né\n".as_bytes());
+        let (editor_rows, _) = round_trip("bom", &bytes);
+        assert!(editor_rows.file_format.bom);
+        assert_eq!(editor_rows.number_of_rows(), 2);
+        assert!(editor_rows.row_content(0).ends_with("a€"));
+        assert_eq!(editor_rows.row_content(1), "é");
+    }
+
+    #[test]
+    fn missing_final_newline_is_kept() {
+        let mut bytes = b"first\r\nsecond\r\n".to_vec();
+        bytes.extend(vec![b'x'; READ_CHUNK]);
+        let (editor_rows, _) = round_trip("no-final-newline", &bytes);
+        assert!(!editor_rows.file_format.final_newline);
+        assert_eq!(editor_rows.number_of_rows(), 3);
+        assert_eq!(editor_rows.row_len(2), READ_CHUNK);
+
+        let (editor_rows, _) = round_trip("empty", b"");
+        assert_eq!(editor_rows.number_of_rows(), 0);
+        let (editor_rows, _) = round_trip("one-char", b"x");
+        assert!(!editor_rows.file_format.final_newline);
+        assert_eq!(editor_rows.row_content(0), "x");
+    }
+
+    #[test]
+    fn mixed_line_endings_are_saved_as_the_most_used_one() {
+        let (editor_rows, mixed) = read("mixed", b"a\r\nb\nc\r\n");
+        assert!(mixed);
+        assert_eq!(editor_rows.file_format.line_ending, LineEnding::Crlf);
+        assert_eq!(written(&editor_rows), b"a\r\nb\r\nc\r\n");
+    }
+}
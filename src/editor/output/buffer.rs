@@ -0,0 +1,32 @@
+use super::{cursor_controller::CursorController, history::History, swap::Swap, EditorRows};
+
+/// A file opened in the editor, with everything about editing it.
+pub(crate) struct Buffer {
+    pub editor_rows: EditorRows,
+    pub cursor_controller: CursorController,
+    pub dirty: u64,
+    pub history: History,
+    pub swap: Swap,
+}
+
+impl Buffer {
+    pub(crate) fn new(editor_rows: EditorRows, win_size: (usize, usize)) -> Self {
+        Self {
+            editor_rows,
+            cursor_controller: CursorController::new(win_size),
+            dirty: 0,
+            history: History::new(),
+            swap: Swap::new(),
+        }
+    }
+
+    /// File name shown in status bar and buffer list.
+    pub(crate) fn name(&self) -> &str {
+        self.editor_rows
+            .filename
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .and_then(|name| name.to_str())
+            .unwrap_or("[No Name]")
+    }
+}
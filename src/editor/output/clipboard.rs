@@ -0,0 +1,238 @@
+use std::{
+    env,
+    io::{self, Write},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use super::super::config;
+use super::EditorContents;
+
+/// Where the copied text goes, and where the pasted text comes from.
+pub(crate) trait ClipboardProvider {
+    /// Store text. Escape sequences for terminal can be written to `out`.
+    fn copy(&mut self, text: &str, out: &mut EditorContents) -> io::Result<()>;
+
+    fn paste(&mut self) -> io::Result<String>;
+}
+
+/// Pick a provider which works in current environment.
+/// 0. Commands set by `copy_command` and `paste_command`.
+/// 1. Wayland or X11: wl-copy / xclip.
+/// 2. Terminal: OSC 52, which also works over SSH.
+/// 3. Otherwise: in process.
+pub(crate) fn detect() -> Box<dyn ClipboardProvider> {
+    let config = config::get();
+    if !config.copy_command.is_empty() || !config.paste_command.is_empty() {
+        return Box::new(CommandClipboard {
+            copy_command: config.copy_command.clone(),
+            paste_command: Some(config.paste_command.clone()).filter(|it| !it.is_empty()),
+            local: InProcessClipboard::default(),
+        });
+    }
+    if env::var_os("WAYLAND_DISPLAY").is_some() && command_exists("wl-copy") {
+        return Box::new(CommandClipboard::new(
+            &["wl-copy"],
+            Some(&["wl-paste", "--no-newline"]),
+        ));
+    }
+    if env::var_os("DISPLAY").is_some() && command_exists("xclip") {
+        return Box::new(CommandClipboard::new(
+            &["xclip", "-selection", "clipboard"],
+            Some(&["xclip", "-selection", "clipboard", "-o"]),
+        ));
+    }
+    if env::var_os("TERM").is_some() {
+        return Box::new(Osc52Clipboard::new());
+    }
+    Box::new(InProcessClipboard::default())
+}
+
+fn command_exists(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|paths| {
+        env::split_paths(&paths).any(|dir| Path::new(&dir).join(program).is_file())
+    })
+}
+
+/// Keeps the copied text in the editor only.
+#[derive(Default)]
+pub(crate) struct InProcessClipboard {
+    text: String,
+}
+
+impl ClipboardProvider for InProcessClipboard {
+    fn copy(&mut self, text: &str, _out: &mut EditorContents) -> io::Result<()> {
+        self.text = text.into();
+        Ok(())
+    }
+
+    fn paste(&mut self) -> io::Result<String> {
+        Ok(self.text.clone())
+    }
+}
+
+/// Sends the copied text to the clipboard of terminal by escape sequence OSC 52.
+/// Terminals rarely allow reading their clipboard, so pasting uses the text copied in the editor.
+pub(crate) struct Osc52Clipboard {
+    local: InProcessClipboard,
+    in_tmux: bool,
+}
+
+impl Osc52Clipboard {
+    pub(crate) fn new() -> Self {
+        Self {
+            local: InProcessClipboard::default(),
+            in_tmux: env::var_os("TMUX").is_some(),
+        }
+    }
+}
+
+impl ClipboardProvider for Osc52Clipboard {
+    fn copy(&mut self, text: &str, out: &mut EditorContents) -> io::Result<()> {
+        let sequence = format!("\x1b]52;c;{}\x07", STANDARD.encode(text));
+        if self.in_tmux {
+            // tmux passes the sequence to the outer terminal when it's wrapped in DCS.
+            out.push_str(&format!("\x1bPtmux;\x1b{}\x1b\\", sequence));
+        } else {
+            out.push_str(&sequence);
+        }
+        self.local.copy(text, out)
+    }
+
+    fn paste(&mut self) -> io::Result<String> {
+        self.local.paste()
+    }
+}
+
+/// Runs external commands, the copied text is written to stdin of `copy_command`,
+/// the pasted text is read from stdout of `paste_command`.
+/// Without the command, the text copied in the editor is used.
+pub(crate) struct CommandClipboard {
+    copy_command: Vec<String>,
+    paste_command: Option<Vec<String>>,
+    local: InProcessClipboard,
+}
+
+impl CommandClipboard {
+    pub(crate) fn new(copy_command: &[&str], paste_command: Option<&[&str]>) -> Self {
+        let to_vec = |command: &[&str]| command.iter().map(|it| it.to_string()).collect();
+        Self {
+            copy_command: to_vec(copy_command),
+            paste_command: paste_command.map(to_vec),
+            local: InProcessClipboard::default(),
+        }
+    }
+}
+
+impl ClipboardProvider for CommandClipboard {
+    fn copy(&mut self, text: &str, out: &mut EditorContents) -> io::Result<()> {
+        self.local.copy(text, out)?;
+        let Some((program, args)) = self.copy_command.split_first() else {
+            return Ok(());
+        };
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        // stdin is closed when it's dropped, so that the command knows the end of text.
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| io::Error::other("stdin of copy command is closed"))?
+            .write_all(text.as_bytes())?;
+        // xclip and wl-copy fork to serve the clipboard, the command itself exits at once.
+        if child.wait()?.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("{} failed", program)))
+        }
+    }
+
+    fn paste(&mut self) -> io::Result<String> {
+        let Some((program, args)) = self.paste_command.as_ref().and_then(|it| it.split_first())
+        else {
+            return self.local.paste();
+        };
+        let output = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!("{} failed", program)));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, process};
+
+    #[test]
+    fn in_process_clipboard_keeps_text() {
+        let mut clipboard = InProcessClipboard::default();
+        let mut out = EditorContents::new();
+        assert_eq!(clipboard.paste().unwrap(), "");
+        clipboard.copy("one\ntwo", &mut out).unwrap();
+        clipboard.copy("three", &mut out).unwrap();
+        assert_eq!(clipboard.paste().unwrap(), "three");
+        assert!(out.content.is_empty());
+    }
+
+    #[test]
+    fn osc52_writes_escape_sequence() {
+        let mut clipboard = Osc52Clipboard {
+            local: InProcessClipboard::default(),
+            in_tmux: false,
+        };
+        let mut out = EditorContents::new();
+        clipboard.copy("hi", &mut out).unwrap();
+        assert_eq!(out.content, "\x1b]52;c;aGk=\x07");
+        assert_eq!(clipboard.paste().unwrap(), "hi");
+
+        clipboard.in_tmux = true;
+        let mut out = EditorContents::new();
+        clipboard.copy("hi", &mut out).unwrap();
+        assert_eq!(out.content, "\x1bPtmux;\x1b\x1b]52;c;aGk=\x07\x1b\\");
+    }
+
+    #[test]
+    fn commands_copy_and_paste() {
+        let file = env::temp_dir().join(format!("pound-clipboard-{}", process::id()));
+        let file = file.to_str().unwrap();
+        let copy = format!("cat > {}", file);
+        let mut clipboard = CommandClipboard::new(&["sh", "-c", &copy], Some(&["cat", file]));
+        clipboard
+            .copy("copied\ntext", &mut EditorContents::new())
+            .unwrap();
+        assert_eq!(fs::read_to_string(file).unwrap(), "copied\ntext");
+        fs::write(file, "from outside").unwrap();
+        assert_eq!(clipboard.paste().unwrap(), "from outside");
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn copied_text_is_pasted_without_paste_command() {
+        // 没有复制命令时只在编辑器里复制
+        let mut clipboard = CommandClipboard::new(&[], None);
+        clipboard.copy("local", &mut EditorContents::new()).unwrap();
+        assert_eq!(clipboard.paste().unwrap(), "local");
+    }
+
+    #[test]
+    fn failed_commands_are_errors() {
+        let mut clipboard = CommandClipboard::new(&["false"], Some(&["false"]));
+        assert!(clipboard.copy("text", &mut EditorContents::new()).is_err());
+        assert!(clipboard.paste().is_err());
+        let mut clipboard = CommandClipboard::new(&["pound-no-such-command"], None);
+        assert!(clipboard.copy("text", &mut EditorContents::new()).is_err());
+        // 复制命令失败时, 文本仍然留在编辑器里
+        assert_eq!(clipboard.paste().unwrap(), "text");
+    }
+}
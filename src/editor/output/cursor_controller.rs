@@ -2,9 +2,48 @@ use std::cmp;
 
 use crossterm::event::KeyCode;
 
-use super::{EditorRows, Row};
+use super::EditorRows;
 
-static TAB_STOP: usize = 8;
+/// How rows wider than the screen are displayed.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Wrap {
+    Off,  // Rows are cut at the edge of screen, which scrolls horizontally.
+    Char, // Rows are broken into lines at the edge of screen.
+    Word, // Rows are broken into lines at word boundaries.
+}
+
+impl Wrap {
+    pub(crate) fn next(self) -> Self {
+        match self {
+            Wrap::Off => Wrap::Char,
+            Wrap::Char => Wrap::Word,
+            Wrap::Word => Wrap::Off,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Wrap::Off => "off",
+            Wrap::Char => "on",
+            Wrap::Word => "at word boundaries",
+        }
+    }
+
+    /// Name in config file.
+    pub(crate) fn key(self) -> &'static str {
+        match self {
+            Wrap::Off => "off",
+            Wrap::Char => "char",
+            Wrap::Word => "word",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        [Wrap::Off, Wrap::Char, Wrap::Word]
+            .into_iter()
+            .find(|it| it.key() == name)
+    }
+}
 
 #[derive(Copy, Clone)] // 我们想保存状态，所以需要 Copy 和 Clone
 pub(crate) struct CursorController {
@@ -13,8 +52,14 @@ pub(crate) struct CursorController {
     screen_columns: usize,
     screen_rows: usize,
     pub row_offset: usize,
+    pub line_offset: usize, // Lines of the wrapped row `row_offset` which are above the screen.
     pub column_offset: usize,
     pub render_x: usize,
+    pub wrap: Wrap,
+    // Cursor position when the view is scrolled away from the cursor by mouse wheel.
+    // The view follows the cursor again once it moves.
+    pub detached: Option<(usize, usize)>,
+    pub selection_anchor: Option<(usize, usize)>, // (x, y) where selection starts, it ends at the cursor.
 }
 
 impl CursorController {
@@ -25,11 +70,47 @@ impl CursorController {
             screen_columns: screen_column,
             screen_rows: screen_row,
             row_offset: 0,
+            line_offset: 0,
             column_offset: 0,
             render_x: 0,
+            wrap: Wrap::Off,
+            detached: None,
+            selection_anchor: None,
+        }
+    }
+
+    /// Selected region as (start, end), start is before end.
+    pub(crate) fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        let anchor = self.selection_anchor?;
+        let cursor = (self.cursor_x, self.cursor_y);
+        if (anchor.1, anchor.0) < (cursor.1, cursor.0) {
+            Some((anchor, cursor))
+        } else if anchor != cursor {
+            Some((cursor, anchor))
+        } else {
+            None
         }
     }
 
+    /// Terminal has been resized, keep the cursor on screen.
+    pub(crate) fn resize(
+        &mut self,
+        (screen_columns, screen_rows): (usize, usize),
+        editor_rows: &EditorRows,
+    ) {
+        self.screen_columns = screen_columns;
+        self.screen_rows = screen_rows;
+        self.scroll(editor_rows);
+    }
+
+    /// The text may have been changed in another view, move the cursor back into the text.
+    pub(crate) fn clamp(&mut self, editor_rows: &EditorRows) {
+        (self.cursor_x, self.cursor_y) = editor_rows.snap_position((self.cursor_x, self.cursor_y));
+        self.selection_anchor = self
+            .selection_anchor
+            .map(|anchor| editor_rows.snap_position(anchor));
+    }
+
     /// KeyCode: KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right | KeyCode::Home | KeyCode::End
     /// usize: row offset in file content
     pub(crate) fn move_cursor(
@@ -40,62 +121,62 @@ impl CursorController {
         let number_of_rows = editor_rows.number_of_rows();
 
         match arrow_key {
+            // 折行时上下移动一个屏幕行, 而不是一个文件行
+            KeyCode::Up if self.wrap != Wrap::Off => self.move_line_up(editor_rows),
+            KeyCode::Down if self.wrap != Wrap::Off => self.move_line_down(editor_rows),
             KeyCode::Up => {
                 self.cursor_y = self.cursor_y.saturating_sub(1);
+                self.cursor_x = self.keep_render_x(editor_rows);
             }
-            KeyCode::Down => {
-                if self.cursor_y < number_of_rows {
-                    //modify
-                    self.cursor_y += 1;
-                }
+            KeyCode::Down if self.cursor_y < number_of_rows => {
+                //modify
+                self.cursor_y += 1;
+                self.cursor_x = self.keep_render_x(editor_rows);
             }
             KeyCode::Left => {
                 if self.cursor_x != 0 {
-                    self.cursor_x -= 1;
-                } else {
-                    if self.cursor_y != 0 {
-                        self.cursor_y -= 1;
-                        self.cursor_x = editor_rows.get_editor_row(self.cursor_y).origin_len();
-                        self.column_offset = editor_rows
-                            .get_editor_row(self.cursor_y)
-                            .origin_len()
-                            .saturating_sub(self.screen_columns);
-                    }
+                    // 光标左移一个字形 (grapheme), 而不是一个字节
+                    self.cursor_x = editor_rows
+                        .get_editor_row(self.cursor_y)
+                        .previous_boundary(self.cursor_x);
+                } else if self.cursor_y != 0 {
+                    self.cursor_y -= 1;
+                    self.cursor_x = editor_rows.row_len(self.cursor_y);
+                    self.column_offset = editor_rows
+                        .get_editor_row(self.cursor_y)
+                        .get_render_x(self.cursor_x)
+                        .saturating_sub(self.screen_columns);
                 }
             }
-            KeyCode::Right => {
-                // 如果光标没有超出文件的最大行数
-                if self.cursor_y < number_of_rows {
-                    // 如果光标没有超出该行的最大列数, 则光标右移
-                    if self.cursor_x < editor_rows.get_editor_row(self.cursor_y).origin_len() {
-                        self.cursor_x += 1;
-                    }
-                    // 如果光标超出该行的最大列数
-                    else {
-                        // 如果光标还没有到达最后一行, 则把光标移动到下一行的第一列
-                        if self.cursor_y < number_of_rows - 1 {
-                            self.cursor_x = 0;
-                            self.cursor_y += 1;
-                        }
+            // 如果光标没有超出文件的最大行数
+            KeyCode::Right if self.cursor_y < number_of_rows => {
+                // 如果光标没有超出该行的最大列数, 则光标右移一个字形 (grapheme)
+                if self.cursor_x < editor_rows.row_len(self.cursor_y) {
+                    self.cursor_x = editor_rows
+                        .get_editor_row(self.cursor_y)
+                        .next_boundary(self.cursor_x);
+                }
+                // 如果光标超出该行的最大列数
+                else {
+                    // 如果光标还没有到达最后一行, 则把光标移动到下一行的第一列
+                    if self.cursor_y < number_of_rows - 1 {
+                        self.cursor_x = 0;
+                        self.cursor_y += 1;
                     }
                 }
             }
-            KeyCode::Home => {
-                if self.cursor_y < number_of_rows {
-                    self.cursor_x = 0;
-                }
+            KeyCode::Home if self.cursor_y < number_of_rows => {
+                self.cursor_x = 0;
             }
-            KeyCode::End => {
-                if self.cursor_y < number_of_rows {
-                    self.cursor_x = editor_rows.get_editor_row(self.cursor_y).render.len();
-                }
+            KeyCode::End if self.cursor_y < number_of_rows => {
+                self.cursor_x = editor_rows.row_len(self.cursor_y);
             }
             _ => {}
         }
 
         // start 考虑光标x坐标是不是落在了空白处
         let row_len = if self.cursor_y < number_of_rows {
-            editor_rows.get_editor_row(self.cursor_y).origin_len()
+            editor_rows.row_len(self.cursor_y)
         } else {
             0
         };
@@ -104,13 +185,238 @@ impl CursorController {
 
         (self.cursor_x, self.cursor_y)
     }
+
+    /// x position in the row of cursor_y which is displayed in the same column as the cursor.
+    fn keep_render_x(&self, editor_rows: &EditorRows) -> usize {
+        if self.cursor_y < editor_rows.number_of_rows() {
+            editor_rows
+                .get_editor_row(self.cursor_y)
+                .get_row_content_x(self.render_x)
+        } else {
+            0
+        }
+    }
+
+    /// Render x at which each line of row `at` starts, a row which isn't wrapped has one line.
+    pub(crate) fn wrap_points(&self, editor_rows: &EditorRows, at: usize) -> Vec<usize> {
+        if self.wrap == Wrap::Off || at >= editor_rows.number_of_rows() {
+            return vec![0];
+        }
+        editor_rows
+            .get_editor_row(at)
+            .wrap_points(self.screen_columns, self.wrap == Wrap::Word)
+    }
+
+    fn line_count(&self, editor_rows: &EditorRows, at: usize) -> usize {
+        self.wrap_points(editor_rows, at).len()
+    }
+
+    /// The line of cursor in its row, and the column of cursor in the line.
+    fn cursor_line(&self, editor_rows: &EditorRows) -> (usize, usize) {
+        let render_x = if self.cursor_y < editor_rows.number_of_rows() {
+            editor_rows
+                .get_editor_row(self.cursor_y)
+                .get_render_x(self.cursor_x)
+        } else {
+            0
+        };
+        let points = self.wrap_points(editor_rows, self.cursor_y);
+        let line = points.partition_point(|&point| point <= render_x) - 1;
+        (line, render_x - points[line])
+    }
+
+    fn move_line_up(&mut self, editor_rows: &EditorRows) {
+        let (line, column) = self.cursor_line(editor_rows);
+        if line > 0 {
+            self.move_to_line(editor_rows, line - 1, column);
+        } else if self.cursor_y > 0 {
+            self.cursor_y -= 1;
+            let last = self.line_count(editor_rows, self.cursor_y) - 1;
+            self.move_to_line(editor_rows, last, column);
+        }
+    }
+
+    fn move_line_down(&mut self, editor_rows: &EditorRows) {
+        if self.cursor_y >= editor_rows.number_of_rows() {
+            return;
+        }
+        let (line, column) = self.cursor_line(editor_rows);
+        if line + 1 < self.line_count(editor_rows, self.cursor_y) {
+            self.move_to_line(editor_rows, line + 1, column);
+        } else {
+            self.cursor_y += 1;
+            self.move_to_line(editor_rows, 0, column);
+        }
+    }
+
+    /// Put the cursor at `column` of line `line` of its row, or at the end of the line if it's shorter.
+    fn move_to_line(&mut self, editor_rows: &EditorRows, line: usize, column: usize) {
+        self.cursor_x = self.x_in_line(editor_rows, self.cursor_y, line, column);
+    }
+
+    /// x position in row `at` which is displayed at `column` of line `line` of the row.
+    fn x_in_line(&self, editor_rows: &EditorRows, at: usize, line: usize, column: usize) -> usize {
+        if at >= editor_rows.number_of_rows() {
+            return 0;
+        }
+        let points = self.wrap_points(editor_rows, at);
+        let mut render_x = points[line] + column;
+        if let Some(&next) = points.get(line + 1) {
+            render_x = cmp::min(render_x, next - 1);
+        }
+        editor_rows.get_editor_row(at).get_row_content_x(render_x)
+    }
+
+    /// Position (x, y) in text which is displayed at `(x, y)` of the area for rows, e.g. where mouse is clicked.
+    /// Below the end of file is the row after the last row.
+    pub(crate) fn position_at(
+        &self,
+        editor_rows: &EditorRows,
+        (x, y): (usize, usize),
+    ) -> (usize, usize) {
+        let screen_lines = self.screen_lines(editor_rows);
+        let (at, line, start, _) = screen_lines[cmp::min(y, screen_lines.len() - 1)];
+        let at = cmp::min(at, editor_rows.number_of_rows());
+        let column = start - self.wrap_points(editor_rows, at)[line] + x;
+        (self.x_in_line(editor_rows, at, line, column), at)
+    }
+
+    /// Scroll the view by `count` lines without moving the cursor, e.g. by mouse wheel.
+    pub(crate) fn scroll_lines(&mut self, editor_rows: &EditorRows, down: bool, count: usize) {
+        for _ in 0..count {
+            if down {
+                if self.line_offset + 1 < self.line_count(editor_rows, self.row_offset) {
+                    self.line_offset += 1;
+                } else if self.row_offset + 1 < editor_rows.number_of_rows() {
+                    self.row_offset += 1;
+                    self.line_offset = 0;
+                }
+            } else if self.line_offset > 0 {
+                self.line_offset -= 1;
+            } else if self.row_offset > 0 {
+                self.row_offset -= 1;
+                self.line_offset = self.line_count(editor_rows, self.row_offset) - 1;
+            }
+        }
+        self.detached = Some((self.cursor_x, self.cursor_y));
+    }
+
+    /// What is displayed on each line of screen, as (row, line in the row, render x where it starts, width).
+    /// Rows after the end of file are included, they have one line.
+    pub(crate) fn screen_lines(
+        &self,
+        editor_rows: &EditorRows,
+    ) -> Vec<(usize, usize, usize, usize)> {
+        if self.wrap == Wrap::Off {
+            return (0..self.screen_rows)
+                .map(|i| {
+                    (
+                        self.row_offset + i,
+                        0,
+                        self.column_offset,
+                        self.screen_columns,
+                    )
+                })
+                .collect();
+        }
+        let mut lines = Vec::with_capacity(self.screen_rows);
+        let mut at = self.row_offset;
+        let mut line = self.line_offset;
+        while lines.len() < self.screen_rows {
+            let points = self.wrap_points(editor_rows, at);
+            for (i, start) in points.iter().enumerate().skip(line) {
+                let width = points
+                    .get(i + 1)
+                    .map_or(self.screen_columns, |next| next - start);
+                lines.push((at, i, *start, width));
+            }
+            at += 1;
+            line = 0;
+        }
+        lines.truncate(self.screen_rows);
+        lines
+    }
+
+    /// Position of cursor on screen, from the top-left of the area for rows.
+    /// None if the cursor isn't on screen, which happens when the view is scrolled by mouse wheel.
+    pub(crate) fn screen_position(&self, editor_rows: &EditorRows) -> Option<(usize, usize)> {
+        if self.cursor_y < self.row_offset || self.cursor_y >= self.row_offset + self.screen_rows {
+            return None;
+        }
+        if self.wrap == Wrap::Off {
+            return Some((
+                self.render_x.checked_sub(self.column_offset)?,
+                self.cursor_y - self.row_offset,
+            ));
+        }
+        let (line, column) = self.cursor_line(editor_rows);
+        let lines = (self.row_offset..self.cursor_y)
+            .map(|at| self.line_count(editor_rows, at))
+            .sum::<usize>();
+        Some((column, (lines + line).checked_sub(self.line_offset)?))
+            .filter(|(_, y)| *y < self.screen_rows)
+    }
+
+    /// Scroll by lines of wrapped rows, so that the line of cursor is on screen.
+    fn scroll_wrapped(&mut self, editor_rows: &EditorRows) {
+        self.column_offset = 0;
+        let (cursor_line, _) = self.cursor_line(editor_rows);
+        // 每个文件行至少占一个屏幕行
+        if self.cursor_y >= self.row_offset + self.screen_rows {
+            self.row_offset = self.cursor_y + 1 - self.screen_rows;
+            self.line_offset = 0;
+        }
+        self.line_offset = cmp::min(
+            self.line_offset,
+            self.line_count(editor_rows, self.row_offset) - 1,
+        );
+        if (self.cursor_y, cursor_line) < (self.row_offset, self.line_offset) {
+            self.row_offset = self.cursor_y;
+            self.line_offset = cursor_line;
+            return;
+        }
+        // Lines from the top of screen to the line of cursor.
+        let mut lines = (self.row_offset..self.cursor_y)
+            .map(|at| self.line_count(editor_rows, at))
+            .sum::<usize>()
+            + cursor_line
+            + 1
+            - self.line_offset;
+        while lines > self.screen_rows {
+            if self.line_offset + 1 < self.line_count(editor_rows, self.row_offset) {
+                self.line_offset += 1;
+            } else {
+                self.row_offset += 1;
+                self.line_offset = 0;
+            }
+            lines -= 1;
+        }
+    }
+
     pub(crate) fn scroll(&mut self, editor_rows: &EditorRows) {
         //
         self.render_x = 0;
+        // 光标所在字符的显示宽度, 宽字符 (例如中文) 占两列
+        let mut cursor_width = 1;
         if self.cursor_y < editor_rows.number_of_rows() {
             // 取得实际的光标的位置
-            self.render_x = self.get_render_x(editor_rows.get_editor_row(self.cursor_y))
+            let row = editor_rows.get_editor_row(self.cursor_y);
+            self.render_x = row.get_render_x(self.cursor_x);
+            cursor_width = cmp::max(
+                row.get_render_x(row.next_boundary(self.cursor_x)) - self.render_x,
+                1,
+            );
+        }
+        // 鼠标滚轮滚动后, 直到光标移动之前视图不跟随光标
+        if self.detached == Some((self.cursor_x, self.cursor_y)) {
+            return;
+        }
+        self.detached = None;
+        if self.wrap != Wrap::Off {
+            self.scroll_wrapped(editor_rows);
+            return;
         }
+        self.line_offset = 0;
         /*
         // 光标超过了屏幕上边界, 则向上滚动一行
         if self.cursor_y < self.row_offset {
@@ -141,20 +447,9 @@ impl CursorController {
         }
         */
         self.column_offset = cmp::min(self.column_offset, self.render_x); //modify
-        if self.render_x >= self.column_offset + self.screen_columns {
+        if self.render_x + cursor_width > self.column_offset + self.screen_columns {
             //modify
-            self.column_offset = self.render_x - self.screen_columns + 1; //modify
+            self.column_offset = (self.render_x + cursor_width).saturating_sub(self.screen_columns);
         }
     }
-    fn get_render_x(&self, row: &Row) -> usize {
-        row.row_content[..self.cursor_x]
-            .chars()
-            .fold(0, |render_x, c| {
-                if c == '\t' {
-                    render_x + (TAB_STOP - 1) - (render_x % TAB_STOP) + 1
-                } else {
-                    render_x + 1
-                }
-            })
-    }
 }
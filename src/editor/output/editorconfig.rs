@@ -0,0 +1,396 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{self, Path},
+};
+
+use regex::Regex;
+
+use super::file_format::LineEnding;
+use super::indent::Indent;
+
+static FILE_NAME: &str = ".editorconfig";
+
+/// Properties from `.editorconfig` files which apply to a file, see https://editorconfig.org.
+/// Files are looked for in the directory of the file and its parents, up to the one with `root = true`.
+/// Nearer files override farther ones, and later sections override earlier ones in a file.
+#[derive(Default)]
+pub(crate) struct EditorConfig {
+    properties: HashMap<String, String>, // Names and values are in lower case.
+}
+
+/// `[glob]` and its properties.
+struct Section {
+    glob: String,
+    properties: Vec<(String, String)>,
+}
+
+impl EditorConfig {
+    /// Properties of `file`, files which can't be read are skipped.
+    pub(crate) fn find(file: &Path) -> Self {
+        let mut editor_config = Self::default();
+        let Ok(file) = path::absolute(file) else {
+            return editor_config;
+        };
+        let mut found = Vec::new();
+        for dir in file.ancestors().skip(1) {
+            let Ok(text) = fs::read_to_string(dir.join(FILE_NAME)) else {
+                continue;
+            };
+            let (root, sections) = parse(&text);
+            found.push((dir, sections));
+            if root {
+                break;
+            }
+        }
+        for (dir, sections) in found.into_iter().rev() {
+            let Ok(relative) = file.strip_prefix(dir) else {
+                continue;
+            };
+            let relative = relative
+                .to_string_lossy()
+                .replace(path::MAIN_SEPARATOR, "/");
+            for section in sections {
+                if glob_regex(&section.glob).is_some_and(|it| it.is_match(&relative)) {
+                    for (name, value) in section.properties {
+                        // "unset" 取消之前设置的值
+                        if value == "unset" {
+                            editor_config.properties.remove(&name);
+                        } else {
+                            editor_config.properties.insert(name, value);
+                        }
+                    }
+                }
+            }
+        }
+        editor_config
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.properties.get(name).map(String::as_str)
+    }
+
+    fn boolean(&self, name: &str) -> Option<bool> {
+        match self.get(name)? {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Indentation from `indent_style` and `indent_size`, `current` is the one without EditorConfig.
+    /// `indent_size` alone changes the width of spaces, but not indentation by tabs.
+    pub(crate) fn indent(&self, current: Indent) -> Option<Indent> {
+        let size = match self.get("indent_size") {
+            Some("tab") => self.tab_width(),
+            Some(size) => size.parse().ok().filter(|it| (1..=16).contains(it)),
+            None => None,
+        };
+        let current_size = match current {
+            Indent::Spaces(width) => Some(width),
+            Indent::Tab => None,
+        };
+        match self.get("indent_style") {
+            Some("tab") => Some(Indent::Tab),
+            Some("space") => Some(Indent::Spaces(size.or(current_size).unwrap_or(4))),
+            _ => current_size.and(size).map(Indent::Spaces),
+        }
+    }
+
+    /// Width of tab, `indent_size` is used if `tab_width` isn't set.
+    pub(crate) fn tab_width(&self) -> Option<usize> {
+        self.get("tab_width")
+            .or(self.get("indent_size"))
+            .and_then(|it| it.parse().ok())
+            .filter(|it| *it > 0)
+    }
+
+    /// Line break of `end_of_line`, "cr" isn't supported.
+    pub(crate) fn line_ending(&self) -> Option<LineEnding> {
+        match self.get("end_of_line")? {
+            "lf" => Some(LineEnding::Lf),
+            "crlf" => Some(LineEnding::Crlf),
+            _ => None,
+        }
+    }
+
+    /// Whether the file starts with BOM, only the UTF-8 charsets are supported.
+    pub(crate) fn bom(&self) -> Option<bool> {
+        match self.get("charset")? {
+            "utf-8" => Some(false),
+            "utf-8-bom" => Some(true),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn trim_trailing_whitespace(&self) -> Option<bool> {
+        self.boolean("trim_trailing_whitespace")
+    }
+
+    pub(crate) fn insert_final_newline(&self) -> Option<bool> {
+        self.boolean("insert_final_newline")
+    }
+}
+
+/// Parse an `.editorconfig` file, which is like
+///     root = true
+///     [*.{rs,toml}]
+///     indent_style = space
+/// Returns whether it's the root file, and the sections in order.
+fn parse(text: &str) -> (bool, Vec<Section>) {
+    let mut root = false;
+    let mut sections: Vec<Section> = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        if let Some(glob) = line.strip_prefix('[').and_then(|it| it.strip_suffix(']')) {
+            sections.push(Section {
+                glob: glob.into(),
+                properties: Vec::new(),
+            });
+            continue;
+        }
+        let Some((name, value)) = line.split_once('=') else {
+            continue;
+        };
+        let (name, value) = (name.trim().to_lowercase(), value.trim().to_lowercase());
+        match sections.last_mut() {
+            Some(section) => section.properties.push((name, value)),
+            // 第一个 section 之前只有 root
+            None if name == "root" => root = value == "true",
+            None => {}
+        }
+    }
+    (root, sections)
+}
+
+/// Regex of a glob in section name, which matches the path of file relative to the `.editorconfig` file.
+/// A glob without '/' matches the file name in any directory.
+fn glob_regex(glob: &str) -> Option<Regex> {
+    let pattern = match glob.strip_prefix('/') {
+        Some(glob) => format!("^{}$", translate(glob)),
+        None if glob.contains('/') => format!("^{}$", translate(glob)),
+        None => format!("^(?:.*/)?{}$", translate(glob)),
+    };
+    Regex::new(&pattern).ok()
+}
+
+/// Translate glob into regex:
+///     *           any chars except '/'
+///     **          any chars, "**/" is zero or more directories
+///     ?           a char except '/'
+///     [abc] [!a]  a char in the set, or not in it
+///     {a,b}       one of the globs
+///     {1..10}     an integer in the range
+fn translate(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut pattern = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                // "a/**/b" 也匹配 "a/b"
+                if chars.get(i + 2) == Some(&'/') {
+                    pattern.push_str("(?:.*/)?");
+                    i += 2;
+                } else {
+                    pattern.push_str(".*");
+                    i += 1;
+                }
+            }
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            '[' => match chars[i + 1..].iter().position(|c| *c == ']') {
+                Some(len) if len > 0 && !chars[i + 1..i + 1 + len].contains(&'/') => {
+                    let mut set = &chars[i + 1..i + 1 + len];
+                    pattern.push('[');
+                    if set[0] == '!' {
+                        pattern.push('^');
+                        set = &set[1..];
+                    }
+                    for c in set {
+                        if matches!(c, '\\' | '[' | '^' | '&' | '~') {
+                            pattern.push('\\');
+                        }
+                        pattern.push(*c);
+                    }
+                    pattern.push(']');
+                    i += len + 1;
+                }
+                _ => pattern.push_str("\\["),
+            },
+            '{' => match closing_brace(&chars[i..]) {
+                Some(len) => {
+                    let inner: String = chars[i + 1..i + len].iter().collect();
+                    pattern.push_str(&translate_braces(&inner));
+                    i += len;
+                }
+                None => pattern.push_str("\\{"),
+            },
+            '\\' if i + 1 < chars.len() => {
+                pattern.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 1;
+            }
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    pattern
+}
+
+/// Index of the '}' which closes the '{' at the start of `chars`.
+fn closing_brace(chars: &[char]) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in chars.iter().enumerate() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Regex of `{inner}`, which is a list of globs or a range of integers. Otherwise the braces are literal.
+fn translate_braces(inner: &str) -> String {
+    if let Some((start, end)) = inner.split_once("..") {
+        if let (Ok(start), Ok(end)) = (start.parse::<i64>(), end.parse::<i64>()) {
+            let numbers: Vec<String> = (start.min(end)..=start.max(end))
+                .take(1000)
+                .map(|it| it.to_string())
+                .collect();
+            return format!("(?:{})", numbers.join("|"));
+        }
+    }
+    // 只在最外层的逗号处分开, 例如 {a,{b,c}}
+    let mut alternatives = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in inner.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                alternatives.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if alternatives.is_empty() {
+        return format!("\\{{{}\\}}", translate(inner));
+    }
+    alternatives.push(&inner[start..]);
+    let alternatives: Vec<String> = alternatives.into_iter().map(translate).collect();
+    format!("(?:{})", alternatives.join("|"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    fn matches(glob: &str, path: &str) -> bool {
+        glob_regex(glob).is_some_and(|it| it.is_match(path))
+    }
+
+    #[test]
+    fn braces() {
+        assert!(matches("*.{rs,toml}", "main.rs"));
+        assert!(matches("*.{rs,toml}", "src/main.rs"));
+        assert!(matches("*.{rs,toml}", "Cargo.toml"));
+        assert!(!matches("*.{rs,toml}", "main.rsx"));
+        assert!(!matches("*.{rs,toml}", "README.md"));
+        assert!(matches("{a,{b,c}}.txt", "c.txt"));
+        assert!(matches("{single}.txt", "{single}.txt"));
+    }
+
+    #[test]
+    fn ranges() {
+        assert!(matches("file{1..3}.txt", "file1.txt"));
+        assert!(matches("file{1..3}.txt", "file3.txt"));
+        assert!(!matches("file{1..3}.txt", "file0.txt"));
+        assert!(!matches("file{1..3}.txt", "file4.txt"));
+        assert!(matches("file{3..1}.txt", "file2.txt"));
+        assert!(matches("file{-1..1}.txt", "file-1.txt"));
+    }
+
+    #[test]
+    fn sets() {
+        assert!(matches("[!a].txt", "b.txt"));
+        assert!(!matches("[!a].txt", "a.txt"));
+        assert!(matches("[ab].txt", "a.txt"));
+        assert!(!matches("[ab].txt", "c.txt"));
+        assert!(!matches("?.txt", "ab.txt"));
+        assert!(matches("[a/b].txt", "[a/b].txt"));
+    }
+
+    #[test]
+    fn slashes() {
+        assert!(matches("/top.txt", "top.txt"));
+        assert!(!matches("/top.txt", "sub/top.txt"));
+        assert!(matches("top.txt", "sub/top.txt"));
+        assert!(matches("sub/*.txt", "sub/a.txt"));
+        assert!(!matches("sub/*.txt", "x/sub/a.txt"));
+        assert!(!matches("sub/*.txt", "sub/d/a.txt"));
+        assert!(matches("a/**/b", "a/b"));
+        assert!(matches("a/**/b", "a/x/b"));
+        assert!(matches("a/**/b", "a/x/y/b"));
+        assert!(!matches("a/**/b", "ab"));
+        assert!(!matches("a/**/b", "a/xb"));
+        assert!(matches("**/b", "b"));
+        assert!(matches("sub/**", "sub/d/a.txt"));
+    }
+
+    #[test]
+    fn parse_sections() {
+        let (root, sections) = parse(
+            "; comment\nroot = TRUE\n[*]\nIndent_Style = Space\n# comment\n[*.md]\ninvalid\nindent_size=2\n",
+        );
+        assert!(root);
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].glob, "*");
+        assert_eq!(
+            sections[0].properties,
+            [("indent_style".to_string(), "space".to_string())]
+        );
+        assert_eq!(
+            sections[1].properties,
+            [("indent_size".to_string(), "2".to_string())]
+        );
+        assert!(!parse("[*]\nroot = true\n").0);
+    }
+
+    #[test]
+    fn find_and_unset() {
+        let dir = env::temp_dir().join(format!("pound-editorconfig-{}", process::id()));
+        let sub = dir.join("sub");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(
+            dir.join(FILE_NAME),
+            "root = true\n[*]\nindent_style = space\ntab_width = 4\n[*.md]\nindent_style = unset\n",
+        )
+        .unwrap();
+        fs::write(sub.join(FILE_NAME), "[*]\ntab_width = 2\n").unwrap();
+
+        let rs = EditorConfig::find(&dir.join("main.rs"));
+        assert_eq!(rs.get("indent_style"), Some("space"));
+        assert_eq!(rs.tab_width(), Some(4));
+        let md = EditorConfig::find(&dir.join("README.md"));
+        assert_eq!(md.get("indent_style"), None);
+        assert!(md.indent(Indent::Tab).is_none());
+        // 更近的文件覆盖上层目录中的设置
+        let nested = EditorConfig::find(&sub.join("lib.rs"));
+        assert_eq!(nested.tab_width(), Some(2));
+        assert_eq!(nested.get("indent_style"), Some("space"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
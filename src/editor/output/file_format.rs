@@ -0,0 +1,51 @@
+/// Line break of the file. Rows in the buffer are always separated by '\n', this is used when saving.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub(crate) enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+        }
+    }
+
+    pub(crate) fn toggled(&self) -> Self {
+        match self {
+            LineEnding::Lf => LineEnding::Crlf,
+            LineEnding::Crlf => LineEnding::Lf,
+        }
+    }
+}
+
+/// How the text is stored in the file, so that saving reproduces it.
+#[derive(Copy, Clone)]
+pub(crate) struct FileFormat {
+    pub line_ending: LineEnding,
+    pub final_newline: bool, // Whether the last row is terminated by a line break.
+    pub bom: bool,           // Whether the file starts with the UTF-8 byte order mark.
+}
+
+impl Default for FileFormat {
+    /// Format of new files.
+    fn default() -> Self {
+        Self {
+            line_ending: LineEnding::Lf,
+            final_newline: true,
+            bom: false,
+        }
+    }
+}
+
+pub(crate) const BOM: &str = "\u{feff}";
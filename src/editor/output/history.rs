@@ -0,0 +1,346 @@
+use super::file_format::LineEnding;
+use super::text_end;
+
+/// A single reversible change of the buffer.
+/// Positions are `(x, y)` where `x` is an offset in `row_content` and `y` is the row index.
+#[derive(Clone, Debug)]
+pub(crate) enum Edit {
+    /// Insert `text` at `at`. `text` may contain `'\n'` which splits the row.
+    Insert { at: (usize, usize), text: String },
+    /// Delete `text` starting at `at`. `text` may contain `'\n'` which joins rows.
+    Delete { at: (usize, usize), text: String },
+    /// Insert an empty row at index `at`.
+    InsertRow { at: usize },
+    /// Remove the empty row at index `at`.
+    DeleteRow { at: usize },
+    /// Change the line break which is used when saving, from `from` to `to`.
+    LineEnding { from: LineEnding, to: LineEnding },
+}
+
+impl Edit {
+    fn inverse(&self) -> Self {
+        match self {
+            Edit::Insert { at, text } => Edit::Delete {
+                at: *at,
+                text: text.clone(),
+            },
+            Edit::Delete { at, text } => Edit::Insert {
+                at: *at,
+                text: text.clone(),
+            },
+            Edit::InsertRow { at } => Edit::DeleteRow { at: *at },
+            Edit::DeleteRow { at } => Edit::InsertRow { at: *at },
+            Edit::LineEnding { from, to } => Edit::LineEnding {
+                from: *to,
+                to: *from,
+            },
+        }
+    }
+
+    /// Where position `(x, y)` moves to when this edit is applied, so that it stays with the text around it.
+    /// A position in deleted text moves to the start of the deletion.
+    pub(crate) fn shift(&self, (x, y): (usize, usize)) -> (usize, usize) {
+        match self {
+            Edit::Insert { at, text } => {
+                if (y, x) < (at.1, at.0) {
+                    (x, y)
+                } else if y == at.1 {
+                    let end = text_end(*at, text);
+                    (end.0 + x - at.0, end.1)
+                } else {
+                    (x, y + text.matches('\n').count())
+                }
+            }
+            Edit::Delete { at, text } => {
+                let end = text_end(*at, text);
+                if (y, x) <= (at.1, at.0) {
+                    (x, y)
+                } else if (y, x) < (end.1, end.0) {
+                    *at
+                } else if y == end.1 {
+                    (at.0 + x - end.0, at.1)
+                } else {
+                    (x, y - text.matches('\n').count())
+                }
+            }
+            Edit::InsertRow { at } if y >= *at => (x, y + 1),
+            Edit::DeleteRow { at } if y > *at => (x, y - 1),
+            Edit::DeleteRow { at } if y == *at => (0, y),
+            _ => (x, y),
+        }
+    }
+}
+
+/// One undo step: edits applied in order, plus the cursor before and after them.
+#[derive(Clone, Debug)]
+pub(crate) struct Change {
+    pub edits: Vec<Edit>,
+    pub cursor_before: (usize, usize),
+    pub cursor_after: (usize, usize),
+}
+
+impl Change {
+    fn inverse(&self) -> Self {
+        Self {
+            edits: self.edits.iter().rev().map(Edit::inverse).collect(),
+            cursor_before: self.cursor_after,
+            cursor_after: self.cursor_before,
+        }
+    }
+
+    /// Append a typed char to this change if it continues right where this change stopped.
+    fn merge(&mut self, other: &Change) -> bool {
+        match (self.edits.last_mut(), other.edits.as_slice()) {
+            (
+                Some(Edit::Insert { at, text }),
+                [Edit::Insert {
+                    at: other_at,
+                    text: other_text,
+                }],
+            ) if !text.contains('\n')
+                && !other_text.contains('\n')
+                && at.1 == other_at.1
+                && at.0 + text.len() == other_at.0 =>
+            {
+                text.push_str(other_text);
+                self.cursor_after = other.cursor_after;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Undo / redo stacks of the buffer.
+#[derive(Default)]
+pub(crate) struct History {
+    undo_stack: Vec<Change>,
+    redo_stack: Vec<Change>,
+    saved_at: Option<usize>, // Length of undo_stack when the buffer was saved. None if it can't be reached anymore.
+    typing: bool,            // Whether the next typed char may be merged into the top change.
+}
+
+impl History {
+    pub(crate) fn new() -> Self {
+        Self {
+            saved_at: Some(0),
+            ..Default::default()
+        }
+    }
+
+    /// Record a change which has already been applied to the buffer.
+    /// If `typing` is true, it's merged into the previous change when it continues it.
+    pub(crate) fn record(&mut self, change: Change, typing: bool) {
+        if self.saved_at.is_some_and(|at| at > self.undo_stack.len()) {
+            self.saved_at = None;
+        }
+        self.redo_stack.clear();
+
+        let can_merge = typing && self.typing && self.saved_at != Some(self.undo_stack.len());
+        self.typing = typing;
+        if can_merge {
+            if let Some(last) = self.undo_stack.last_mut() {
+                if last.merge(&change) {
+                    return;
+                }
+            }
+        }
+        self.undo_stack.push(change);
+    }
+
+    /// Stop merging typed chars into the current change.
+    pub(crate) fn seal(&mut self) {
+        self.typing = false;
+    }
+
+    /// Returns the change which reverts the last change.
+    pub(crate) fn undo(&mut self) -> Option<Change> {
+        self.typing = false;
+        let change = self.undo_stack.pop()?;
+        let inverse = change.inverse();
+        self.redo_stack.push(change);
+        Some(inverse)
+    }
+
+    /// Returns the last undone change.
+    pub(crate) fn redo(&mut self) -> Option<Change> {
+        self.typing = false;
+        let change = self.redo_stack.pop()?;
+        self.undo_stack.push(change.clone());
+        Some(change)
+    }
+
+    pub(crate) fn mark_saved(&mut self) {
+        self.typing = false;
+        self.saved_at = Some(self.undo_stack.len());
+    }
+
+    /// Whether the buffer is in the same state as when it was saved.
+    pub(crate) fn is_saved(&self) -> bool {
+        self.saved_at == Some(self.undo_stack.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(at: (usize, usize), text: &str) -> Change {
+        Change {
+            edits: vec![Edit::Insert {
+                at,
+                text: text.into(),
+            }],
+            cursor_before: at,
+            cursor_after: text_end(at, text),
+        }
+    }
+
+    /// Text of the only edit of `change`.
+    fn text_of(change: &Change) -> &str {
+        match change.edits.as_slice() {
+            [Edit::Insert { text, .. } | Edit::Delete { text, .. }] => text,
+            edits => panic!("not a single edit: {:?}", edits),
+        }
+    }
+
+    #[test]
+    fn typed_chars_are_merged() {
+        let mut history = History::new();
+        history.record(insert((0, 0), "a"), true);
+        history.record(insert((1, 0), "b"), true);
+        // 不相连, 或者不是输入的字符, 都不合并
+        history.record(insert((5, 0), "c"), true);
+        history.record(insert((6, 0), "d"), false);
+        history.record(insert((7, 0), "\n"), true);
+
+        assert_eq!(text_of(&history.undo().unwrap()), "\n");
+        assert_eq!(text_of(&history.undo().unwrap()), "d");
+        assert_eq!(text_of(&history.undo().unwrap()), "c");
+        let undo = history.undo().unwrap();
+        assert!(matches!(&undo.edits[..], [Edit::Delete { at: (0, 0), text }] if text == "ab"));
+        assert_eq!((undo.cursor_before, undo.cursor_after), ((2, 0), (0, 0)));
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn seal_stops_merging() {
+        let mut history = History::new();
+        history.record(insert((0, 0), "a"), true);
+        history.seal();
+        history.record(insert((1, 0), "b"), true);
+        assert_eq!(text_of(&history.undo().unwrap()), "b");
+    }
+
+    #[test]
+    fn saved_state_is_tracked() {
+        let mut history = History::new();
+        assert!(history.is_saved());
+        history.record(insert((0, 0), "a"), true);
+        assert!(!history.is_saved());
+        history.mark_saved();
+        // 保存之后输入的字符不合并到保存之前的修改里
+        history.record(insert((1, 0), "b"), true);
+        assert!(!history.is_saved());
+        history.undo();
+        assert!(history.is_saved());
+        history.undo();
+        assert!(!history.is_saved());
+        history.redo();
+        assert!(history.is_saved());
+
+        // 撤销到保存之前再做别的修改, 保存时的状态就回不去了
+        history.undo();
+        history.record(insert((0, 0), "x"), false);
+        history.undo();
+        assert!(!history.is_saved());
+        assert!(history.redo().is_some());
+        assert!(history.redo().is_none());
+        history.mark_saved();
+        assert!(history.is_saved());
+    }
+
+    #[test]
+    fn redo_applies_the_undone_change() {
+        let mut history = History::new();
+        history.record(insert((0, 0), "a\nb"), false);
+        history.undo();
+        let redo = history.redo().unwrap();
+        assert!(matches!(&redo.edits[..], [Edit::Insert { at: (0, 0), text }] if text == "a\nb"));
+        assert_eq!(redo.cursor_after, (1, 1));
+        assert!(history.redo().is_none());
+        // 新的修改清空重做栈
+        history.undo();
+        history.record(insert((0, 0), "c"), false);
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn inverse_reverts_edits_in_reverse_order() {
+        let change = Change {
+            edits: vec![
+                Edit::InsertRow { at: 2 },
+                Edit::LineEnding {
+                    from: LineEnding::Lf,
+                    to: LineEnding::Crlf,
+                },
+            ],
+            cursor_before: (1, 1),
+            cursor_after: (0, 2),
+        };
+        let inverse = change.inverse();
+        assert!(matches!(
+            &inverse.edits[..],
+            [
+                Edit::LineEnding {
+                    from: LineEnding::Crlf,
+                    to: LineEnding::Lf
+                },
+                Edit::DeleteRow { at: 2 }
+            ]
+        ));
+        assert_eq!(
+            (inverse.cursor_before, inverse.cursor_after),
+            ((0, 2), (1, 1))
+        );
+    }
+
+    #[test]
+    fn positions_are_shifted_by_insert() {
+        let edit = Edit::Insert {
+            at: (2, 1),
+            text: "xy\nz".into(),
+        };
+        assert_eq!(edit.shift((5, 0)), (5, 0));
+        assert_eq!(edit.shift((1, 1)), (1, 1));
+        assert_eq!(edit.shift((2, 1)), (1, 2));
+        assert_eq!(edit.shift((4, 1)), (3, 2));
+        assert_eq!(edit.shift((0, 3)), (0, 4));
+    }
+
+    #[test]
+    fn positions_are_shifted_by_delete() {
+        let edit = Edit::Delete {
+            at: (2, 1),
+            text: "xy\nz".into(),
+        };
+        assert_eq!(edit.shift((2, 1)), (2, 1));
+        assert_eq!(edit.shift((3, 1)), (2, 1));
+        assert_eq!(edit.shift((0, 2)), (2, 1));
+        assert_eq!(edit.shift((3, 2)), (4, 1));
+        assert_eq!(edit.shift((7, 4)), (7, 3));
+    }
+
+    #[test]
+    fn positions_are_shifted_by_rows() {
+        assert_eq!(Edit::InsertRow { at: 1 }.shift((3, 0)), (3, 0));
+        assert_eq!(Edit::InsertRow { at: 1 }.shift((3, 1)), (3, 2));
+        assert_eq!(Edit::DeleteRow { at: 1 }.shift((3, 1)), (0, 1));
+        assert_eq!(Edit::DeleteRow { at: 1 }.shift((3, 2)), (3, 1));
+        let line_ending = Edit::LineEnding {
+            from: LineEnding::Lf,
+            to: LineEnding::Crlf,
+        };
+        assert_eq!(line_ending.shift((3, 2)), (3, 2));
+    }
+}
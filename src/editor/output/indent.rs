@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+
+use ropey::Rope;
+
+static DETECT_ROWS: usize = 10000; // Only the first rows are looked at when detecting indentation.
+
+/// What one level of indentation is made of.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Indent {
+    Tab,
+    Spaces(usize),
+}
+
+impl Indent {
+    /// Text of one level.
+    pub(crate) fn unit(self) -> String {
+        match self {
+            Indent::Tab => "\t".into(),
+            Indent::Spaces(width) => " ".repeat(width),
+        }
+    }
+
+    /// Columns taken by one level.
+    pub(crate) fn width(self, tab_stop: usize) -> usize {
+        match self {
+            Indent::Tab => tab_stop,
+            Indent::Spaces(width) => width,
+        }
+    }
+
+    /// Shown in status bar.
+    pub(crate) fn name(self) -> String {
+        match self {
+            Indent::Tab => "tabs".into(),
+            Indent::Spaces(width) => format!("{} spaces", width),
+        }
+    }
+
+    /// Indentation inserted by Tab at column `render_x`, which goes to the next level.
+    pub(crate) fn soft_tab(self, render_x: usize) -> String {
+        match self {
+            Indent::Tab => "\t".into(),
+            Indent::Spaces(width) => " ".repeat(width - render_x % width),
+        }
+    }
+
+    /// Length of the whitespace which is removed from the start of `row` to dedent it by one level.
+    pub(crate) fn dedent_len(self, row: &str, tab_stop: usize) -> usize {
+        if row.starts_with('\t') {
+            return 1;
+        }
+        let spaces = row.len() - row.trim_start_matches(' ').len();
+        let width = self.width(tab_stop);
+        // 缩进不是整数级时, 先退到上一级
+        match spaces % width {
+            0 => spaces.min(width),
+            rest => rest,
+        }
+    }
+
+    /// Spaces removed by Backspace when `before_cursor` is the part of row before the cursor.
+    /// Returns None unless the cursor is in the leading spaces, where a whole level is removed.
+    pub(crate) fn backspace_len(self, before_cursor: &str) -> Option<usize> {
+        let Indent::Spaces(width) = self else {
+            return None;
+        };
+        if before_cursor.is_empty() || before_cursor.bytes().any(|it| it != b' ') {
+            return None;
+        }
+        Some((before_cursor.len() - 1) % width + 1)
+    }
+}
+
+/// Guess the indentation of `text` from the leading whitespace of rows.
+/// Tabs win if more rows are indented by tabs, otherwise the width of spaces is the most common change of
+/// indentation between a row and the previous indented one. Returns None if no row is indented.
+pub(crate) fn detect(text: &Rope) -> Option<Indent> {
+    let (mut tabs, mut spaces) = (0, 0);
+    let mut changes: BTreeMap<usize, usize> = BTreeMap::new(); // Change of indentation -> times.
+    let mut previous = 0;
+    for line in text.lines().take(DETECT_ROWS) {
+        let mut chars = line.chars();
+        match chars.next() {
+            Some('\t') => tabs += 1,
+            Some(' ') => {
+                let width = 1 + chars.by_ref().take_while(|c| *c == ' ').count();
+                // 忽略空白行, 以及像块注释中 " * " 这样只缩进一个空格的行
+                if line.chars().nth(width).is_none_or(char::is_whitespace) || width == 1 {
+                    continue;
+                }
+                spaces += 1;
+                let change = width.abs_diff(previous);
+                if change > 1 {
+                    *changes.entry(change).or_default() += 1;
+                }
+                previous = width;
+            }
+            Some(c) if !c.is_whitespace() => previous = 0,
+            _ => {}
+        }
+    }
+    if tabs > spaces {
+        return Some(Indent::Tab);
+    }
+    changes
+        .into_iter()
+        .filter(|(change, _)| *change <= 8)
+        .max_by_key(|(change, times)| (*times, std::cmp::Reverse(*change)))
+        .map(|(change, _)| Indent::Spaces(change))
+}
+
+/// How a language indents rows when Enter is pressed, every `SyntaxHighlight` provides it.
+/// The defaults suit languages with C-like brackets.
+pub trait IndentRules {
+    /// Pairs of brackets, rows after an open bracket are indented by one more level.
+    fn brackets(&self) -> &[(char, char)] {
+        &[('{', '}'), ('(', ')'), ('[', ']')]
+    }
+
+    /// Whether the row after `before`, the part of row before the cursor, is indented by one more level.
+    fn indents_after(&self, before: &str) -> bool {
+        before
+            .trim_end()
+            .chars()
+            .next_back()
+            .is_some_and(|c| self.brackets().iter().any(|(open, _)| *open == c))
+    }
+
+    /// Whether the row is split between a pair of brackets, `after` is the part of row after the cursor.
+    /// The closing bracket is moved to its own row then.
+    fn splits_pair(&self, before: &str, after: &str) -> bool {
+        match (
+            before.trim_end().chars().next_back(),
+            after.trim_start().chars().next(),
+        ) {
+            (Some(open), Some(close)) => self.brackets().contains(&(open, close)),
+            _ => false,
+        }
+    }
+}
+
+/// Rules of files without syntax highlight, which only keep the indentation of the previous row.
+pub(crate) struct PlainText;
+
+impl IndentRules for PlainText {
+    fn brackets(&self) -> &[(char, char)] {
+        &[]
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, Metadata},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Write a file safely: contents go to a temporary file in the same directory,
+/// which is synced to disk and then renamed over the file. The file is either the old one or the new one,
+/// even if the editor crashes or the disk is full in the middle of writing.
+/// Permissions and ownership of the old file are kept. If the owner can't be kept, e.g. a group-writable file
+/// of another user, the old file is copied to `file~` and then overwritten in place.
+/// If `backup` is true, the old file is always copied to `file~`.
+/// `write_contents` writes the contents, and returns the number of bytes written.
+pub(crate) fn write_file(
+    path: &Path,
+    backup: bool,
+    write_contents: impl Fn(&mut dyn Write) -> io::Result<usize>,
+) -> io::Result<Written> {
+    // Write to the target of a symbolic link, so that the link is kept.
+    let path = match fs::canonicalize(path) {
+        Ok(path) => path,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+        Err(err) => return Err(err),
+    };
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => Some(metadata),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err),
+    };
+    if backup && metadata.is_some() {
+        fs::copy(&path, with_suffix(&path, "~"))?;
+    }
+
+    let temp = with_suffix(&path, &format!(".{}.tmp", process::id()));
+    let result = File::options()
+        .write(true)
+        .create_new(true)
+        .open(&temp)
+        .and_then(|file| {
+            if let Some(metadata) = &metadata {
+                file.set_permissions(metadata.permissions())?;
+                if !keep_owner(&file, metadata) {
+                    return Ok(None);
+                }
+            }
+            let written = write_and_sync(file, &write_contents)?;
+            fs::rename(&temp, &path)?;
+            sync_parent(&path);
+            Ok(Some(written))
+        });
+    match result {
+        Ok(Some(bytes)) => Ok(Written {
+            bytes,
+            in_place: None,
+        }),
+        Ok(None) => {
+            let _ = fs::remove_file(&temp);
+            // 新文件不能交给原来的所有者, 先备份, 再写回原来的文件, 所有者和权限都不变
+            let backup = with_suffix(&path, "~");
+            fs::copy(&path, &backup)?;
+            let file = File::options().write(true).truncate(true).open(&path)?;
+            let bytes = write_and_sync(file, &write_contents)?;
+            Ok(Written {
+                bytes,
+                in_place: Some(backup),
+            })
+        }
+        Err(err) => {
+            let _ = fs::remove_file(&temp);
+            Err(err)
+        }
+    }
+}
+
+/// Result of `write_file`.
+pub struct Written {
+    pub bytes: usize,
+    pub in_place: Option<PathBuf>, // The owner couldn't be kept, the file has been overwritten in place after it's copied to this backup.
+}
+
+fn write_and_sync(
+    file: File,
+    write_contents: &impl Fn(&mut dyn Write) -> io::Result<usize>,
+) -> io::Result<usize> {
+    let mut writer = BufWriter::new(file);
+    let written = write_contents(&mut writer)?;
+    let file = writer.into_inner().map_err(|err| err.into_error())?;
+    file.sync_all()?;
+    Ok(written)
+}
+
+/// `path` with `suffix` appended to its file name, e.g. `main.rs~`.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_name().map(OsString::from).unwrap_or_default();
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+/// Give the new file the owner of the old one, returns false if it can't be changed.
+fn keep_owner(file: &File, metadata: &Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{fchown, MetadataExt};
+        let Ok(current) = file.metadata() else {
+            return false;
+        };
+        if (current.uid(), current.gid()) != (metadata.uid(), metadata.gid()) {
+            return fchown(file, Some(metadata.uid()), Some(metadata.gid())).is_ok();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (file, metadata);
+    true
+}
+
+/// Make the rename durable, it's recorded in the directory.
+fn sync_parent(path: &Path) {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        let _ = File::open(parent).and_then(|dir| dir.sync_all());
+    }
+}
@@ -0,0 +1,284 @@
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+use super::{is_grapheme_boundary, EditorRows};
+
+static COUNT_LIMIT: usize = 1000; // Matches are counted up to this number for "n of m".
+static COUNT_BYTES: usize = 1 << 20; // Matches are counted in this much text at most, so that typing isn't slowed down by a big buffer.
+
+/// Toggles of searching, they are kept for the next search.
+#[derive(Copy, Clone, Default)]
+pub(crate) struct SearchOptions {
+    pub regex: bool, // Keyword is a regular expression, otherwise it's literal text.
+    pub ignore_case: bool, // Case-insensitive.
+    pub whole_word: bool, // Matches must start and end at word boundaries.
+}
+
+impl SearchOptions {
+    /// Regex which finds `keyword` with these options.
+    pub(crate) fn compile(&self, keyword: &str) -> Result<Regex, regex::Error> {
+        let mut pattern = if self.regex {
+            keyword.to_string()
+        } else {
+            regex::escape(keyword)
+        };
+        if self.whole_word {
+            pattern = format!(r"\b(?:{})\b", pattern);
+        }
+        RegexBuilder::new(&pattern)
+            .case_insensitive(self.ignore_case)
+            .build()
+    }
+}
+
+/// State of the search in progress.
+/// Matches aren't kept, they are searched from the cursor when moving to one, and in rows when they are drawn.
+#[derive(Default)]
+pub(crate) struct Search {
+    pub options: SearchOptions,
+    pub origin: (usize, usize), // Cursor position when the search started, the first match is searched from it.
+    pattern: Option<Regex>,
+    error: Option<String>,
+    current: Option<(usize, Range<usize>)>, // (row, range in row_content) of the match where the cursor is.
+    count: Count,
+    wrapped: bool,
+}
+
+/// Matches counted from the start of the buffer for "n of m".
+#[derive(Default)]
+struct Count {
+    index: Option<usize>, // Index of the current match, None if counting stopped before reaching it.
+    total: usize,         // Matches counted, up to COUNT_LIMIT + 1.
+    complete: bool,       // All matches in the buffer have been counted.
+}
+
+impl Search {
+    /// Compile `keyword` with current options, the matches are searched by `jump`.
+    pub(crate) fn update(&mut self, keyword: &str) {
+        self.clear();
+        if keyword.is_empty() {
+            return;
+        }
+        match self.options.compile(keyword) {
+            Ok(regex) => self.pattern = Some(regex),
+            Err(_) => self.error = Some("invalid regex".into()),
+        }
+    }
+
+    /// Matches in `row_content`.
+    /// Empty matches (e.g. `^`) can't be highlighted, matches which split a grapheme can't be selected by cursor.
+    pub(crate) fn matches_in_row<'a>(
+        &'a self,
+        row_content: &'a str,
+    ) -> impl Iterator<Item = Range<usize>> + 'a {
+        self.pattern
+            .iter()
+            .flat_map(move |regex| regex.find_iter(row_content))
+            .filter(move |it| {
+                !it.is_empty()
+                    && is_grapheme_boundary(row_content, it.start())
+                    && is_grapheme_boundary(row_content, it.end())
+            })
+            .map(|it| it.range())
+    }
+
+    /// Move to the first match at or after `(x, y)`, or the last match before it when `backward`.
+    /// Search continues from the other end of the buffer when there is no such match.
+    /// Returns the start of the match.
+    pub(crate) fn jump(
+        &mut self,
+        (x, y): (usize, usize),
+        backward: bool,
+        editor_rows: &EditorRows,
+    ) -> Option<(usize, usize)> {
+        let number_of_rows = editor_rows.number_of_rows();
+        self.current = None;
+        self.wrapped = false;
+        if self.pattern.is_none() || number_of_rows == 0 {
+            return None;
+        }
+        let y = y.min(number_of_rows - 1);
+        // 从光标所在的行开始, 绕过缓冲区的另一端, 最后回到光标所在的行
+        for step in 0..=number_of_rows {
+            let row = if backward {
+                (y + number_of_rows - step) % number_of_rows
+            } else {
+                (y + step) % number_of_rows
+            };
+            let row_content = editor_rows.row_content(row);
+            let found = {
+                // 在光标所在的行, 开始时只看光标的一侧, 绕回来时只看另一侧
+                let mut matches = self.matches_in_row(&row_content).filter(|it| match step {
+                    0 => (it.start >= x) != backward,
+                    _ if step == number_of_rows => (it.start >= x) == backward,
+                    _ => true,
+                });
+                if backward {
+                    matches.last()
+                } else {
+                    matches.next()
+                }
+            };
+            if let Some(range) = found {
+                self.wrapped = if backward {
+                    step > y
+                } else {
+                    y + step >= number_of_rows
+                };
+                self.current = Some((row, range));
+                break;
+            }
+        }
+        self.count = self.count(editor_rows);
+        self.current_match()
+    }
+
+    /// Move to the match after (or before) the current one.
+    pub(crate) fn step(
+        &mut self,
+        backward: bool,
+        editor_rows: &EditorRows,
+    ) -> Option<(usize, usize)> {
+        let (y, range) = self.current.clone()?;
+        if backward {
+            self.jump((range.start, y), true, editor_rows)
+        } else {
+            self.jump((range.start + 1, y), false, editor_rows)
+        }
+    }
+
+    fn current_match(&self) -> Option<(usize, usize)> {
+        self.current.as_ref().map(|(y, range)| (range.start, *y))
+    }
+
+    /// Count matches from the start of the buffer, it's done on every keystroke.
+    /// Counting stops after COUNT_LIMIT matches or COUNT_BYTES of text, whichever comes first.
+    fn count(&self, editor_rows: &EditorRows) -> Count {
+        let mut count = Count::default();
+        let Some((current_row, current_range)) = &self.current else {
+            return count;
+        };
+        let mut searched = 0;
+        for y in 0..editor_rows.number_of_rows() {
+            if searched >= COUNT_BYTES {
+                return count;
+            }
+            let row_content = editor_rows.row_content(y);
+            searched += row_content.len() + 1;
+            for range in self.matches_in_row(&row_content) {
+                if y == *current_row && range.start == current_range.start {
+                    count.index = Some(count.total);
+                }
+                count.total += 1;
+                if count.total > COUNT_LIMIT {
+                    return count;
+                }
+            }
+        }
+        count.complete = true;
+        count
+    }
+
+    /// Forget the matches, options and origin are kept.
+    pub(crate) fn clear(&mut self) {
+        *self = Self {
+            options: self.options,
+            origin: self.origin,
+            ..Default::default()
+        };
+    }
+
+    /// Text shown after the prompt: toggles, "n of m" and whether the search has wrapped.
+    pub(crate) fn status(&self) -> String {
+        let options = [
+            (self.options.regex, "regex"),
+            (self.options.ignore_case, "ignore case"),
+            (self.options.whole_word, "whole word"),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(", ");
+        let mut status = if let Some(error) = &self.error {
+            error.clone()
+        } else if self.pattern.is_none() {
+            String::new()
+        } else if self.current.is_some() {
+            // 没有数完时, 显示至少有多少个匹配
+            match self.count {
+                Count {
+                    index: Some(index),
+                    total,
+                    complete: true,
+                } => format!("{} of {}", index + 1, total),
+                Count {
+                    index: Some(index),
+                    total,
+                    ..
+                } => format!(
+                    "{} of {}+",
+                    index + 1,
+                    total.min(COUNT_LIMIT).max(index + 1)
+                ),
+                Count { total, .. } => format!("{}+ matches", (total + 1).min(COUNT_LIMIT)),
+            }
+        } else {
+            "no matches".into()
+        };
+        if self.wrapped {
+            status.push_str(", search wrapped");
+        }
+        if !options.is_empty() {
+            status = format!("[{}] {}", options, status);
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ropey::Rope;
+
+    fn search_in(text: &str, keyword: &str, at: (usize, usize)) -> (Search, EditorRows) {
+        let mut editor_rows = EditorRows::empty(None);
+        editor_rows.text = Rope::from_str(text);
+        let mut search = Search::default();
+        search.update(keyword);
+        search.jump(at, false, &editor_rows);
+        (search, editor_rows)
+    }
+
+    #[test]
+    fn matches_are_counted() {
+        let (mut search, editor_rows) = search_in("a a\nb a\n", "a", (1, 0));
+        assert_eq!(search.status(), "2 of 3");
+        search.step(false, &editor_rows);
+        assert_eq!(search.status(), "3 of 3");
+        search.step(false, &editor_rows);
+        assert_eq!(search.status(), "1 of 3, search wrapped");
+        let (search, _) = search_in("a\n", "b", (0, 0));
+        assert_eq!(search.status(), "no matches");
+    }
+
+    #[test]
+    fn counting_stops_after_limit() {
+        let text = "a\n".repeat(COUNT_LIMIT + 500);
+        let (search, _) = search_in(&text, "a", (0, 0));
+        assert_eq!(search.status(), format!("1 of {}+", COUNT_LIMIT));
+        let (search, _) = search_in(&text, "a", (0, COUNT_LIMIT + 100));
+        assert_eq!(search.status(), format!("{}+ matches", COUNT_LIMIT));
+    }
+
+    #[test]
+    fn counting_stops_in_big_buffer() {
+        // 很长的一行之后的匹配不再计数
+        let text = format!("x\n{}\nx\n", "b".repeat(COUNT_BYTES));
+        let (search, _) = search_in(&text, "x", (0, 0));
+        assert_eq!(search.status(), "1 of 1+");
+        let (search, _) = search_in(&text, "x", (0, 1));
+        assert_eq!(search.status(), "2+ matches");
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process,
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use ropey::Rope;
+
+use crate::editor::config;
+
+static HEADER: &str = "pound swap file";
+
+/// Swap file of the edited file, it keeps a copy of the unsaved buffer in case the editor dies.
+/// The file is `.name.swp` next to the edited file, or `.name.swo` if `.name.swp` belongs to another editor:
+///     pound swap file
+///     pid: <process id of the editor>
+///     <contents of buffer, rows are terminated by '\n'>
+pub(crate) struct Swap {
+    path: Option<PathBuf>, // Where the swap file has been written, None if there isn't one.
+    written_at: Option<Instant>,
+    written_dirty: u64,  // `Buffer::dirty` when the swap file was written.
+    pub alternate: bool, // `.name.swp` belongs to another editor, write to `.name.swo` instead.
+    writer: Option<JoinHandle<io::Result<()>>>, // Thread which writes the swap file, typing isn't blocked by a big buffer.
+}
+
+/// Swap file which is left by another editor.
+pub(crate) struct StaleSwap {
+    pub path: PathBuf,
+    pub pid: Option<u32>,
+    pub running: bool, // The process which wrote it is still running, e.g. the file is edited in another terminal.
+}
+
+impl Swap {
+    pub(crate) fn new() -> Self {
+        Self {
+            path: None,
+            written_at: None,
+            written_dirty: 0,
+            alternate: false,
+            writer: None,
+        }
+    }
+
+    /// Write the buffer to swap file if it has changed and the last write isn't too recent.
+    /// The swap file is removed when there are no unsaved changes.
+    pub(crate) fn update(
+        &mut self,
+        file: Option<&Path>,
+        text: &Rope,
+        dirty: u64,
+    ) -> io::Result<()> {
+        let path = file.map(|file| swap_path(file, self.alternate));
+        if path != self.path || dirty == 0 {
+            self.remove();
+        }
+        let (Some(file), Some(path)) = (file, path) else {
+            return Ok(());
+        };
+        // 上一次还没写完时不等它, 下一个 tick 再写
+        if self.writer.as_ref().is_some_and(|it| !it.is_finished()) {
+            return Ok(());
+        }
+        let result = self.join();
+        if dirty == 0
+            || dirty == self.written_dirty
+            || self
+                .written_at
+                .is_some_and(|at| at.elapsed() < config::get().swap_interval)
+        {
+            return result;
+        }
+        self.written_at = Some(Instant::now());
+        self.written_dirty = dirty;
+        self.path = Some(path.clone());
+        // Cloning a rope is cheap, the text is shared with the buffer.
+        let (text, mode) = (text.clone(), swap_mode(file));
+        self.writer = Some(thread::spawn(move || write_swap(&path, &text, mode)));
+        result
+    }
+
+    /// Wait for the thread which writes the swap file, and get the result.
+    fn join(&mut self) -> io::Result<()> {
+        match self.writer.take().map(JoinHandle::join) {
+            None => Ok(()),
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(io::Error::other("writing swap file panicked")),
+        }
+    }
+
+    /// Remove the swap file written by this editor, e.g. the buffer has been saved or the editor quits.
+    pub(crate) fn remove(&mut self) {
+        // 等写入结束, 否则交换文件会在删除之后又出现
+        let _ = self.join();
+        if let Some(path) = self.path.take() {
+            let _ = fs::remove_file(path);
+        }
+        self.written_at = None;
+        self.written_dirty = 0;
+    }
+}
+
+/// `.name.swp` in the directory of `file`, or `.name.swo` if it's the `alternate` one.
+fn swap_path(file: &Path, alternate: bool) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(file.file_name().unwrap_or_default());
+    name.push(if alternate { ".swo" } else { ".swp" });
+    file.with_file_name(name)
+}
+
+/// Write `text` to `path` through a temporary file which is renamed over it, so the swap file is never half written.
+/// It isn't synced to disk: the swap file is for the editor dying, and syncing a big buffer takes long.
+fn write_swap(path: &Path, text: &Rope, mode: u32) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", process::id()));
+    let temp = PathBuf::from(temp);
+    let mut options = File::options();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+    #[cfg(not(unix))]
+    let _ = mode;
+    let result = options.open(&temp).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write!(writer, "{}\npid: {}\n", HEADER, process::id())?;
+        for chunk in text.chunks() {
+            writer.write_all(chunk.as_bytes())?;
+        }
+        writer.flush()?;
+        fs::rename(&temp, path)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Permissions of the swap file: the same as `file`, which may be readable by others,
+/// or only the owner can read it if `file` doesn't exist yet.
+fn swap_mode(file: &Path) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(metadata) = fs::metadata(file) {
+            return metadata.permissions().mode() & 0o666;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = file;
+    0o600
+}
+
+/// Swap file of `file` which is left by another editor.
+pub(crate) fn find_stale(file: &Path) -> Option<StaleSwap> {
+    let path = swap_path(file, false);
+    let mut reader = BufReader::new(File::open(&path).ok()?);
+    let mut header = String::new();
+    reader.read_line(&mut header).ok()?;
+    if header.trim_end() != HEADER {
+        return None;
+    }
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let pid = line
+        .trim_end()
+        .strip_prefix("pid: ")
+        .and_then(|pid| pid.parse().ok());
+    Some(StaleSwap {
+        path,
+        pid,
+        running: pid.is_some_and(is_running),
+    })
+}
+
+/// Whether process `pid` exists, only known on Linux.
+fn is_running(pid: u32) -> bool {
+    pid != process::id() && Path::new("/proc").join(pid.to_string()).exists()
+}
+
+impl StaleSwap {
+    /// Contents of buffer in the swap file.
+    pub(crate) fn read(&self) -> io::Result<Rope> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut line = String::new();
+        for _ in 0..2 {
+            reader.read_line(&mut line)?;
+        }
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        Ok(Rope::from_str(&text))
+    }
+
+    pub(crate) fn discard(&self) -> io::Result<()> {
+        fs::remove_file(&self.path)
+    }
+}
+
+/// Describe where the rows of `swap` differ from `text`, only the first and last differing rows are found.
+pub(crate) fn diff_summary(text: &Rope, swap: &Rope) -> String {
+    let (rows, swap_rows) = (text.len_lines() - 1, swap.len_lines() - 1);
+    let same = |a: usize, b: usize| text.line(a) == swap.line(b);
+    let prefix = (0..rows.min(swap_rows)).take_while(|&i| same(i, i)).count();
+    if prefix == rows && prefix == swap_rows {
+        return "Swap file is the same as the file".into();
+    }
+    let suffix = (1..=(rows.min(swap_rows) - prefix))
+        .take_while(|&i| same(rows - i, swap_rows - i))
+        .count();
+    format!(
+        "Swap file differs from line {}: {} line(s) in the file, {} line(s) in swap file",
+        prefix + 1,
+        rows - suffix - prefix,
+        swap_rows - suffix - prefix
+    )
+}
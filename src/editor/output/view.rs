@@ -0,0 +1,280 @@
+use super::cursor_controller::CursorController;
+
+/// A rectangle on screen, in terminal cells.
+#[derive(Copy, Clone)]
+pub(crate) struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub(crate) fn contains(&self, x: usize, y: usize) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+
+    /// A view in an empty rect isn't drawn, e.g. the terminal has shrunk too much for all views.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum SplitDirection {
+    Horizontal, // One view above the other.
+    Vertical,   // Side by side, separated by a column of '│'.
+}
+
+/// Line numbers shown at the left of rows.
+#[derive(Copy, Clone)]
+pub(crate) enum LineNumbers {
+    Off,
+    Absolute,
+    Relative, // Distance from the row of cursor.
+}
+
+impl LineNumbers {
+    pub(crate) fn next(self) -> Self {
+        match self {
+            LineNumbers::Off => LineNumbers::Absolute,
+            LineNumbers::Absolute => LineNumbers::Relative,
+            LineNumbers::Relative => LineNumbers::Off,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            LineNumbers::Off => "off",
+            LineNumbers::Absolute => "absolute",
+            LineNumbers::Relative => "relative",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        [
+            LineNumbers::Off,
+            LineNumbers::Absolute,
+            LineNumbers::Relative,
+        ]
+        .into_iter()
+        .find(|it| it.name() == name)
+    }
+}
+
+/// A viewport onto a buffer, with its own cursor and scroll position.
+/// Several views may show the same buffer.
+#[derive(Copy, Clone)]
+pub(crate) struct View {
+    pub buffer: usize, // Index in buffer list.
+    // For the focused view this is out of date, its cursor is the one of `Output::buffer`.
+    pub cursor_controller: CursorController,
+}
+
+/// How the screen is split into views.
+/// Views are numbered in the order they appear in the tree, which is from top-left to bottom-right.
+pub(crate) enum Layout {
+    View(View),
+    Split {
+        direction: SplitDirection,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    pub(crate) fn count(&self) -> usize {
+        match self {
+            Layout::View(_) => 1,
+            Layout::Split { first, second, .. } => first.count() + second.count(),
+        }
+    }
+
+    pub(crate) fn views(&self) -> Vec<&View> {
+        match self {
+            Layout::View(view) => vec![view],
+            Layout::Split { first, second, .. } => {
+                let mut views = first.views();
+                views.extend(second.views());
+                views
+            }
+        }
+    }
+
+    pub(crate) fn views_mut(&mut self) -> Vec<&mut View> {
+        match self {
+            Layout::View(view) => vec![view],
+            Layout::Split { first, second, .. } => {
+                let mut views = first.views_mut();
+                views.extend(second.views_mut());
+                views
+            }
+        }
+    }
+
+    /// Where each view is drawn when the layout fills `area`, in the order of views.
+    /// Every view takes its last line for status bar.
+    pub(crate) fn rects(&self, area: Rect) -> Vec<Rect> {
+        match self {
+            Layout::View(_) => vec![area],
+            Layout::Split {
+                direction,
+                first,
+                second,
+            } => {
+                let (first_area, second_area) = split_rect(area, *direction);
+                let mut rects = first.rects(first_area);
+                rects.extend(second.rects(second_area));
+                rects
+            }
+        }
+    }
+
+    /// Columns between the views of vertical splits, which are drawn as separators.
+    pub(crate) fn separators(&self, area: Rect) -> Vec<Rect> {
+        match self {
+            Layout::View(_) => Vec::new(),
+            Layout::Split {
+                direction,
+                first,
+                second,
+            } => {
+                let (first_area, second_area) = split_rect(area, *direction);
+                let mut separators = first.separators(first_area);
+                if let (SplitDirection::Vertical, false) = (direction, area.is_empty()) {
+                    separators.push(Rect {
+                        x: first_area.x + first_area.width,
+                        width: 1,
+                        ..area
+                    });
+                }
+                separators.extend(second.separators(second_area));
+                separators
+            }
+        }
+    }
+
+    /// Split view `index` into two, the new view `view` is put after (below or right of) it.
+    pub(crate) fn split(&mut self, index: usize, direction: SplitDirection, view: View) {
+        match self {
+            Layout::View(old) => {
+                let old = *old;
+                *self = Layout::Split {
+                    direction,
+                    first: Box::new(Layout::View(old)),
+                    second: Box::new(Layout::View(view)),
+                };
+            }
+            Layout::Split { first, second, .. } => {
+                let count = first.count();
+                if index < count {
+                    first.split(index, direction, view)
+                } else {
+                    second.split(index - count, direction, view)
+                }
+            }
+        }
+    }
+
+    /// Remove view `index`, its space is given to the view or views next to it.
+    /// The only view can't be closed, returns false in this case.
+    pub(crate) fn close(&mut self, index: usize) -> bool {
+        let Layout::Split { first, second, .. } = self else {
+            return false;
+        };
+        let count = first.count();
+        let (child, sibling, index) = if index < count {
+            (first, second, index)
+        } else {
+            (second, first, index - count)
+        };
+        match **child {
+            Layout::View(view) => {
+                let sibling = std::mem::replace(sibling.as_mut(), Layout::View(view));
+                *self = sibling;
+                true
+            }
+            Layout::Split { .. } => child.close(index),
+        }
+    }
+}
+
+/// Split `area` in half, a vertical split leaves a column between the halves for separator.
+fn split_rect(area: Rect, direction: SplitDirection) -> (Rect, Rect) {
+    match direction {
+        SplitDirection::Horizontal => {
+            let height = area.height / 2;
+            (
+                Rect { height, ..area },
+                Rect {
+                    y: area.y + height,
+                    height: area.height - height,
+                    ..area
+                },
+            )
+        }
+        SplitDirection::Vertical => {
+            let width = area.width.saturating_sub(1) / 2;
+            (
+                Rect { width, ..area },
+                Rect {
+                    x: area.x + (width + 1).min(area.width),
+                    width: area.width.saturating_sub(width + 1),
+                    ..area
+                },
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Views split horizontally, then vertically, then horizontally again.
+    fn layout() -> Layout {
+        let view = View {
+            buffer: 0,
+            cursor_controller: CursorController::new((80, 24)),
+        };
+        let mut layout = Layout::View(view);
+        layout.split(0, SplitDirection::Horizontal, view);
+        layout.split(1, SplitDirection::Vertical, view);
+        layout.split(2, SplitDirection::Horizontal, view);
+        layout
+    }
+
+    #[test]
+    fn rects_stay_in_small_area() {
+        // 终端缩小之后, 视图可能没有空间, 但不能超出区域
+        for (width, height) in [(0, 0), (1, 0), (40, 1), (2, 2), (1, 5), (3, 4)] {
+            let area = Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            };
+            let layout = layout();
+            let rects = layout.rects(area);
+            assert_eq!(rects.len(), 4);
+            for rect in rects.iter().chain(&layout.separators(area)) {
+                assert!(rect.x + rect.width <= width && rect.y + rect.height <= height);
+                if !rect.is_empty() {
+                    assert!(area.contains(rect.x, rect.y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_views_are_found() {
+        let rects = layout().rects(Rect {
+            x: 0,
+            y: 0,
+            width: 40,
+            height: 1,
+        });
+        assert!(rects[0].is_empty());
+        assert_eq!(rects.iter().filter(|rect| rect.is_empty()).count(), 2);
+    }
+}
@@ -1,17 +1,37 @@
 use std::time::Duration;
 
-use crossterm::event::{self, poll, Event, KeyEvent};
+use crossterm::event::{self, poll, Event, KeyEvent, KeyEventKind, MouseEvent, MouseEventKind};
 
 /// This is a producer.
 pub struct Reader;
 
 impl Reader {
-    pub fn read_key(&self) -> crossterm::Result<KeyEvent> {
+    /// Read the next event which the editor handles: key pressing, terminal resizing, pasted text
+    /// or mouse clicking, dragging and scrolling.
+    /// Returns None if there is no event for a while (a tick), so that the editor can do things in background.
+    pub fn read_event(&self) -> crossterm::Result<Option<Event>> {
         loop {
             if poll(Duration::from_millis(500))? {
-                if let Ok(Event::Key(event)) = event::read() {
-                    return Ok(event);
+                // 有的终端也会报告按键释放 (Release), 只处理按下
+                if let event @ (Event::Key(KeyEvent {
+                    kind: KeyEventKind::Press | KeyEventKind::Repeat,
+                    ..
+                })
+                | Event::Resize(..)
+                | Event::Paste(_)
+                | Event::Mouse(MouseEvent {
+                    kind:
+                        MouseEventKind::Down(_)
+                        | MouseEventKind::Drag(_)
+                        | MouseEventKind::ScrollUp
+                        | MouseEventKind::ScrollDown,
+                    ..
+                })) = event::read()?
+                {
+                    return Ok(Some(event));
                 }
+            } else {
+                return Ok(None);
             }
         }
     }
@@ -1,24 +1,43 @@
-use crossterm::terminal;
+use std::{io::stdout, thread};
+
+use crossterm::{
+    event::{EnableBracketedPaste, EnableMouseCapture},
+    execute,
+    terminal::{self, EnterAlternateScreen},
+};
 use editor::output::Output;
 
 #[macro_use]
 extern crate lazy_static;
 
+mod crash;
 pub mod editor;
 
 struct Cleaner;
 
 impl Drop for Cleaner {
     fn drop(&mut self) {
-        terminal::disable_raw_mode().expect("Could not turn off raw mode");
-        Output::clear_screen().expect("Error"); /* add this line*/
+        // 发生 panic 时终端已经被 panic hook 恢复, 不要清除屏幕上的错误信息
+        if !thread::panicking() {
+            Output::clear_screen().expect("Error"); /* add this line*/
+        }
+        crash::restore_terminal();
     }
 }
 
 fn main() -> crossterm::Result<()> {
+    crash::install_panic_hook();
     let _cleaner = Cleaner;
     terminal::enable_raw_mode()?; // enable raw mode
 
+    // 粘贴的文本作为一个整体 (Event::Paste) 到达, 而不是一个个按键
+    execute!(
+        stdout(),
+        EnterAlternateScreen,
+        EnableBracketedPaste,
+        EnableMouseCapture
+    )?;
+
     let mut editor = editor::Editor::new();
     while editor.run()? {}
 